@@ -0,0 +1,25 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use kerfadjusterlogic::collapse_contours;
+use kerfadjusterlogic::options::OffsetOptions;
+
+#[path = "../tests/fixtures/tiling.rs"]
+mod tiling;
+use tiling::tiled_example;
+
+fn bench_collapse_contours(c: &mut Criterion) {
+    let tolerance = OffsetOptions::default().join_tolerance;
+    let mut group = c.benchmark_group("collapse_contours");
+    group.sample_size(10);
+    for copies in [1, 10, 50] {
+        let contours = tiled_example(copies);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(contours.len()),
+            &contours,
+            |b, contours| b.iter(|| collapse_contours(contours.clone(), tolerance).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_collapse_contours);
+criterion_main!(benches);
@@ -0,0 +1,213 @@
+//! Cleanup of offset contours that fold back over themselves
+//!
+//! When the offset is large compared to a feature (like a narrow slot, or a small notch), the offset pieces on either
+//! side of the feature move past each other, and the offset contour crosses over itself. The loops that this makes
+//! come closer to the original contour than the offset distance, which is how they are found and removed.
+use crate::contour::Contour;
+use crate::offset::Piece;
+use nalgebra::Vector3;
+
+/// A piece of the offset contour after it has been cut up wherever other pieces cross it
+struct SubPiece {
+    /// Index of the offset piece that this was cut from
+    parent: usize,
+    piece: Piece,
+}
+
+/// How far along `piece` a point is, or `None` if the point is not on the piece
+fn position_on(piece: &Piece, pt: &Vector3<f64>) -> Option<f64> {
+    let along = piece.shape.distance_along(pt);
+    if along >= -Contour::EPSILON && along <= piece.shape.length() + Contour::EPSILON {
+        Some(along)
+    } else {
+        None
+    }
+}
+
+/// Cuts the pieces up at every point where another piece crosses them
+fn split_at_crossings(pieces: Vec<Piece>) -> Vec<SubPiece> {
+    let mut cuts = vec![Vec::new(); pieces.len()];
+    for i in 0..pieces.len() {
+        for j in (i + 1)..pieces.len() {
+            // Pieces that lie on top of each other do not cross, so they are cut where one ends part way along the
+            // other instead
+            let (a, b) = (&pieces[i].shape, &pieces[j].shape);
+            let touching_ends = vec![a.start(), a.end(), b.start(), b.end()]
+                .into_iter()
+                .filter(|pt| {
+                    a.distance_to(pt) < Contour::EPSILON && b.distance_to(pt) < Contour::EPSILON
+                });
+
+            for pt in a.intersections(b).into_iter().chain(touching_ends) {
+                if let (Some(along_i), Some(along_j)) =
+                    (position_on(&pieces[i], &pt), position_on(&pieces[j], &pt))
+                {
+                    cuts[i].push((along_i, pt));
+                    cuts[j].push((along_j, pt));
+                }
+            }
+        }
+    }
+
+    let mut sub_pieces = Vec::with_capacity(pieces.len());
+    for (parent, (piece, mut piece_cuts)) in pieces.into_iter().zip(cuts).enumerate() {
+        // Pieces are already separate where they meet end to end
+        let length = piece.shape.length();
+        piece_cuts
+            .retain(|(along, _)| *along > Contour::EPSILON && *along < length - Contour::EPSILON);
+        piece_cuts.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+        piece_cuts.dedup_by(|(a, _), (b, _)| (*a - *b).abs() < Contour::EPSILON);
+
+        let mut start = piece.shape.start();
+        for (_, pt) in piece_cuts {
+            sub_pieces.push(SubPiece {
+                parent,
+                piece: Piece {
+                    shape: piece.shape.clone().with_start(start).with_end(pt),
+                    ..piece.clone()
+                },
+            });
+            start = pt;
+        }
+        sub_pieces.push(SubPiece {
+            parent,
+            piece: Piece {
+                shape: piece.shape.clone().with_start(start),
+                ..piece
+            },
+        });
+    }
+    sub_pieces
+}
+
+/// Whether the piece stays far enough away from the original contour. Gaps of up to `tolerance` in the original
+/// contour can bring it that much closer to the offset pieces.
+fn is_clear_of(piece: &Piece, original: &[Piece], tolerance: f64) -> bool {
+    let (midpoint, _) = piece.shape.midpoint();
+    original
+        .iter()
+        .all(|o| o.shape.distance_to(&midpoint) >= piece.clearance - tolerance)
+}
+
+/// Removes pieces that lie on top of each other. Two pieces that go the same way are kept once, and two that go
+/// opposite ways cancel each other out.
+fn remove_overlaps(sub_pieces: Vec<SubPiece>) -> Vec<SubPiece> {
+    let is_near = |a: Vector3<f64>, b: Vector3<f64>| (a - b).magnitude() < Contour::EPSILON;
+    let mut kept: Vec<SubPiece> = Vec::with_capacity(sub_pieces.len());
+    for sub in sub_pieces {
+        let shape = &sub.piece.shape;
+        let same_midpoint =
+            |other: &SubPiece| is_near(other.piece.shape.midpoint().0, shape.midpoint().0);
+        let duplicate = kept.iter().any(|other| {
+            same_midpoint(other)
+                && is_near(other.piece.shape.start(), shape.start())
+                && is_near(other.piece.shape.end(), shape.end())
+        });
+        let opposite = kept.iter().position(|other| {
+            same_midpoint(other)
+                && is_near(other.piece.shape.start(), shape.end())
+                && is_near(other.piece.shape.end(), shape.start())
+        });
+
+        match opposite {
+            Some(i) => {
+                kept.remove(i);
+            }
+            None if !duplicate => kept.push(sub),
+            None => (),
+        }
+    }
+    kept
+}
+
+/// Links pieces up end to end into closed loops, following the order that they were in wherever there is a choice.
+/// Ends that are within `tolerance` of each other count as meeting.
+///
+/// Returns `None` if some of the pieces cannot be made into a closed loop.
+fn chain_loops(sub_pieces: Vec<SubPiece>, tolerance: f64) -> Option<Vec<Vec<SubPiece>>> {
+    let count = sub_pieces.len();
+    let mut remaining = sub_pieces.into_iter().map(Some).collect::<Vec<_>>();
+    let mut loops = Vec::new();
+
+    while let Some(first) = remaining.iter().position(Option::is_some) {
+        let mut chain = vec![(first, remaining[first].take()?)];
+        while let Some((last_index, end)) = chain.last().map(|(i, sub)| (*i, sub.piece.shape.end()))
+        {
+            // Coming back to a corner of the chain closes a loop. If that corner is not where the chain started, the
+            // rest of the chain carries on from there.
+            let loop_start = chain
+                .iter()
+                .position(|(_, sub)| (sub.piece.shape.start() - end).magnitude() < tolerance);
+            if let Some(loop_start) = loop_start {
+                // Close up whatever gap is left between the ends
+                let start = chain[loop_start].1.piece.shape.start();
+                let (_, last) = chain.last_mut()?;
+                last.piece.shape = last.piece.shape.clone().with_end(start);
+                loops.push(
+                    chain
+                        .split_off(loop_start)
+                        .into_iter()
+                        .map(|(_, sub)| sub)
+                        .collect(),
+                );
+                continue;
+            }
+
+            let next = (1..count).map(|k| (last_index + k) % count).find(|&i| {
+                remaining[i]
+                    .as_ref()
+                    .is_some_and(|sub| (sub.piece.shape.start() - end).magnitude() < tolerance)
+            })?;
+            let mut sub = remaining[next].take()?;
+            sub.piece.shape = sub.piece.shape.with_start(end);
+            chain.push((next, sub));
+        }
+    }
+
+    Some(loops)
+}
+
+/// Puts back together the sub-pieces that were cut from the same piece and are still next to each other
+fn merge_sub_pieces(offset_loop: Vec<SubPiece>) -> Vec<Piece> {
+    let mut merged: Vec<SubPiece> = Vec::with_capacity(offset_loop.len());
+    for sub in offset_loop {
+        match merged.last_mut() {
+            Some(prev) if prev.parent == sub.parent => {
+                prev.piece.shape = prev.piece.shape.clone().with_end(sub.piece.shape.end());
+            }
+            _ => merged.push(sub),
+        }
+    }
+
+    // The loop might start part way through a piece
+    if merged.len() > 1 && merged[0].parent == merged[merged.len() - 1].parent {
+        let last = merged.pop().unwrap();
+        merged[0].piece.shape = last.piece.shape.with_end(merged[0].piece.shape.end());
+    }
+
+    merged.into_iter().map(|sub| sub.piece).collect()
+}
+
+/// Removes the loops where an offset contour crosses over itself
+///
+/// `original` are the pieces of the contour before it was offset, and `offset` are the offset pieces in the order
+/// they are walked. Returns the closed loops that are left over, or `None` if what is left over cannot be joined up
+/// into closed loops with ends that are within `tolerance` of each other.
+pub fn remove_self_intersections(
+    original: &[Piece],
+    offset: Vec<Piece>,
+    tolerance: f64,
+) -> Option<Vec<Vec<Piece>>> {
+    let clear_pieces = split_at_crossings(offset)
+        .into_iter()
+        .filter(|sub| is_clear_of(&sub.piece, original, tolerance))
+        .collect::<Vec<_>>();
+    let clear_pieces = remove_overlaps(clear_pieces);
+
+    Some(
+        chain_loops(clear_pieces, tolerance)?
+            .into_iter()
+            .map(merge_sub_pieces)
+            .collect(),
+    )
+}
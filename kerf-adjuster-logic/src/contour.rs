@@ -1,12 +1,24 @@
+use crate::ellipse::ellipse_endpoints;
 use crate::errors::{KerfAdjustmentError, KerfAdjustmentErrorReason};
+use crate::ocs::{arc_to_wcs, entity_to_wcs, is_flat, vector_to_wcs};
+use crate::offset::{offset_closed_contour, outline_open_contour};
+use crate::options::OffsetOptions;
+use crate::polyline::{
+    is_2d_polyline, lwpolyline_along, lwpolyline_endpoints, lwpolyline_is_closed, polyline_endpoints,
+    polyline_is_closed,
+};
+use crate::report::{DisappearedFeature, OffsetWarning, OpenPath};
+use crate::segment::{ContourSegment, Segment};
+use crate::spline::{spline_endpoints, spline_has_curve};
 use dxf::entities::*;
 use dxf::Drawing;
 use dxf::Point;
 use dxf::Vector;
 use nalgebra::Vector3;
+use std::rc::Rc;
 
 /// This is a newtype that exists solely to convert nalgebra vectors to/from DXF crate counterparts
-struct VectorWrapper(Vector3<f64>);
+pub(crate) struct VectorWrapper(pub(crate) Vector3<f64>);
 
 impl From<Vector> for VectorWrapper {
     fn from(vec: Vector) -> Self {
@@ -40,25 +52,65 @@ impl From<VectorWrapper> for Vector {
     }
 }
 
-pub fn find_endpoints_of_entity(e: &Entity) -> Option<(Vector3<f64>, Vector3<f64>)> {
-    // TODO: real error handling for unsupported entities - Result instead of Option
+/// What an entity is, as far as joining it up with other entities into contours goes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EntityEnds {
+    /// The entity goes from the first point to the second, and can be joined to other entities at either of them
+    Open(Vector3<f64>, Vector3<f64>),
+    /// The entity comes back around to where it started (like a circle), so it is a contour by itself
+    Closed,
+    /// The entity is not cut (like text, dimensions or images), so it is not part of any contour and goes into the
+    /// offset drawing as it is
+    PassThrough,
+}
+
+impl EntityEnds {
+    /// The start and end of the entity, if it has any
+    pub fn open_ends(self) -> Option<(Vector3<f64>, Vector3<f64>)> {
+        match self {
+            EntityEnds::Open(start, end) => Some((start, end)),
+            _ => None,
+        }
+    }
+}
+
+/// Works out whether an entity is open (and where its ends are), closed, or not cut at all.
+///
+/// Entities that cannot be cut by a 2D machine are an error: `ThreeDimensionalEntity` for ones that do not lie flat
+/// in the XY plane, and `UnsupportedEntity` for kinds of entities that cannot be offset.
+pub fn find_endpoints_of_entity(e: &Entity) -> Result<EntityEnds, KerfAdjustmentError> {
+    let error = |reason| KerfAdjustmentError {
+        reason,
+        entity: Some(Box::new(e.common.clone())),
+    };
+    let three_dimensional = || error(KerfAdjustmentErrorReason::ThreeDimensionalEntity);
+    let open_or_closed = |ends: Option<(Vector3<f64>, Vector3<f64>)>| match ends {
+        Some((start, end)) => EntityEnds::Open(start, end),
+        None => EntityEnds::Closed,
+    };
 
     match &e.specific {
-        EntityType::Circle(_) => None, // Circles are their own contour
+        EntityType::Circle(circle) => {
+            if !is_flat(&circle.normal) {
+                return Err(three_dimensional());
+            }
+            Ok(EntityEnds::Closed) // Circles are their own contour
+        }
         EntityType::Line(line) => {
             // convert line endpoints into vectors
             let (VectorWrapper(start), VectorWrapper(end)) =
                 (line.p1.clone().into(), line.p2.clone().into());
-            Some((start, end))
+            // A line that climbs out of the XY plane cannot be cut, but one that is raised off of it can
+            if (start.z - end.z).abs() > Contour::EPSILON {
+                return Err(three_dimensional());
+            }
+            Ok(EntityEnds::Open(start, end))
         }
         EntityType::Arc(arc) => {
+            // Arcs that are not parallel to the XY plane cannot be cut
+            let arc = arc_to_wcs(arc).ok_or_else(three_dimensional)?;
             let VectorWrapper(normal_vec) = arc.normal.clone().into();
 
-            // TODO: figure out the 3d math to make work
-            if normal_vec != Vector3::new(0., 0., 1.) {
-                return None;
-            }
-
             let VectorWrapper(center) = arc.center.clone().into();
 
             // start and end angles are counter-clockwise from x axis, like radians
@@ -81,21 +133,131 @@ pub fn find_endpoints_of_entity(e: &Entity) -> Option<(Vector3<f64>, Vector3<f64
                 center + end_rot.transform_vector(&circle_axis),
             );
 
-            Some((start_pt, end_pt))
+            Ok(EntityEnds::Open(start_pt, end_pt))
         }
-        EntityType::Text(_) | EntityType::MText(_) => None,
-        _ => todo!(),
+        EntityType::LwPolyline(poly) => {
+            if !is_flat(&poly.extrusion_direction) {
+                return Err(three_dimensional());
+            }
+            // Closed polylines are their own contour
+            if lwpolyline_is_closed(poly) {
+                Ok(EntityEnds::Closed)
+            } else {
+                let normal = &poly.extrusion_direction;
+                let ends = lwpolyline_endpoints(poly)
+                    .map(|(start, end)| (vector_to_wcs(normal, &start), vector_to_wcs(normal, &end)));
+                Ok(open_or_closed(ends))
+            }
+        }
+        EntityType::Polyline(poly) => {
+            if !is_2d_polyline(poly) || !is_flat(&poly.normal) {
+                return Err(three_dimensional());
+            }
+            if polyline_is_closed(poly) {
+                Ok(EntityEnds::Closed)
+            } else {
+                let normal = &poly.normal;
+                let ends = polyline_endpoints(poly)
+                    .map(|(start, end)| (vector_to_wcs(normal, &start), vector_to_wcs(normal, &end)));
+                Ok(open_or_closed(ends))
+            }
+        }
+        EntityType::Spline(spline) => {
+            // Splines are in world coordinates, so a flat one has all of its control points at the same height
+            let is_level = spline.control_points.windows(2).all(|pair| {
+                (pair[0].z - pair[1].z).abs() <= Contour::EPSILON
+            });
+            if !is_flat(&spline.normal) || !is_level {
+                return Err(three_dimensional());
+            }
+            // A spline that is only defined by fit points (or is not valid) has no curve to offset
+            if !spline_has_curve(spline) {
+                return Err(error(KerfAdjustmentErrorReason::UnsupportedEntity(Box::new(
+                    e.specific.clone(),
+                ))));
+            }
+            // Closed splines are their own contour
+            Ok(open_or_closed(spline_endpoints(spline)))
+        }
+        EntityType::Ellipse(ellipse) => {
+            if !is_flat(&ellipse.normal) {
+                return Err(three_dimensional());
+            }
+            Ok(open_or_closed(ellipse_endpoints(ellipse)))
+        }
+        EntityType::Text(_)
+        | EntityType::MText(_)
+        | EntityType::ArcAlignedText(_)
+        | EntityType::RText(_)
+        | EntityType::Attribute(_)
+        | EntityType::AttributeDefinition(_)
+        | EntityType::RotatedDimension(_)
+        | EntityType::RadialDimension(_)
+        | EntityType::DiameterDimension(_)
+        | EntityType::AngularThreePointDimension(_)
+        | EntityType::OrdinateDimension(_)
+        | EntityType::Leader(_)
+        | EntityType::Tolerance(_)
+        | EntityType::Shape(_)
+        | EntityType::ModelPoint(_)
+        | EntityType::Image(_)
+        | EntityType::Wipeout(_)
+        | EntityType::OleFrame(_)
+        | EntityType::Ole2Frame(_)
+        | EntityType::DgnUnderlay(_)
+        | EntityType::DwfUnderlay(_)
+        | EntityType::PdfUnderlay(_)
+        | EntityType::Light(_)
+        // Fills and construction lines are not profiles to be cut
+        | EntityType::Solid(_)
+        | EntityType::Trace(_)
+        | EntityType::XLine(_)
+        | EntityType::Ray(_)
+        // Entities that this library does not know about (like hatches) are kept as they are
+        | EntityType::ProxyEntity(_) => Ok(EntityEnds::PassThrough),
+        specific => Err(error(KerfAdjustmentErrorReason::UnsupportedEntity(
+            Box::new(specific.clone()),
+        ))),
     }
 }
 
-enum ContourSides {
-    Inside,
-    Outside,
+/// The start and end of an entity, or `None` if it is closed, is not cut, or cannot be cut
+///
+/// A line always has a start and an end, even if it climbs out of the XY plane. Drawings with lines like that are
+/// turned down by `find_endpoints_of_entity` before any contours are made.
+pub fn open_ends_of_entity(e: &Entity) -> Option<(Vector3<f64>, Vector3<f64>)> {
+    match &e.specific {
+        EntityType::Line(line) => {
+            let (VectorWrapper(start), VectorWrapper(end)) =
+                (line.p1.clone().into(), line.p2.clone().into());
+            Some((start, end))
+        }
+        _ => find_endpoints_of_entity(e).ok()?.open_ends(),
+    }
 }
 
+/// Length of the diagonal of the box that all of the contours fit in, or 0 if there is nothing in them to measure
+pub fn extents_size(contours: &[Contour]) -> f64 {
+    let mut points = contours
+        .iter()
+        .flat_map(|c| c.segments.iter())
+        .flat_map(|segment| segment.shape.extent_points());
+    let first = match points.next() {
+        Some(first) => first,
+        None => return 0.,
+    };
+    let (min, max) = points.fold((first, first), |(min, max), pt| {
+        (min.inf(&pt), max.sup(&pt))
+    });
+    (max - min).magnitude()
+}
+
+/// A chain of segments, each of which starts where the one before it ends
+///
+/// An open contour is walked from the first of its end points to the second. A closed contour ends where it started.
 #[derive(Clone)]
 pub struct Contour {
-    pub entities: Vec<Entity>,
+    pub segments: Vec<ContourSegment>,
     // Can be none if the contour is closed
     pub end_points: Option<(Vector3<f64>, Vector3<f64>)>,
 }
@@ -103,7 +265,7 @@ pub struct Contour {
 impl std::fmt::Debug for Contour {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
         f.debug_struct("Contour")
-            .field("entities", &self.entities.len())
+            .field("segments", &self.segments.len())
             .field("end_points", &self.end_points)
             .finish()
     }
@@ -116,21 +278,84 @@ impl From<Entity> for Contour {
 }
 
 impl Contour {
+    /// Makes a contour out of the segments of an entity. Entities that are not made of lines and arcs (which should
+    /// have been approximated by polylines by now) make a contour with nothing in it.
     pub fn create_from_entity(e: Entity) -> Self {
-        return Self {
-            end_points: find_endpoints_of_entity(&e),
-            entities: vec![e],
-        };
+        // Everything past this point works in world coordinates
+        let e = entity_to_wcs(e);
+
+        Self {
+            end_points: open_ends_of_entity(&e),
+            segments: ContourSegment::of_entity(e),
+        }
     }
 
     pub fn is_open(&self) -> bool {
-        return self.end_points.is_some();
+        self.end_points.is_some()
     }
 
-    // TODO: epsilon should be configurable
-    const EPSILON: f64 = 1e-6;
+    /// The layer that the contour is on, which is the layer of the entity its first segment came from
+    pub fn layer(&self) -> &str {
+        self.segments
+            .first()
+            .map_or("", |segment| segment.source.common.layer.as_str())
+    }
+
+    /// The same contour, walked the other way
+    pub fn reversed(self) -> Self {
+        Self {
+            segments: self
+                .segments
+                .into_iter()
+                .rev()
+                .map(ContourSegment::reversed)
+                .collect(),
+            end_points: self.end_points.map(|(a, b)| (b, a)),
+        }
+    }
 
-    pub fn combine_attempt(self, other: Self) -> Result<Self, (Self, Self)> {
+    /// Whether a point lies within the area enclosed by this contour.
+    ///
+    /// Open contours do not enclose any area, so they never contain anything.
+    pub fn contains_point(&self, pt: &Vector3<f64>) -> bool {
+        if self.is_open() {
+            return false;
+        }
+
+        // Even-odd rule: the point is inside if a ray cast from it crosses the contour an odd number of times
+        let crossings: usize = self
+            .segments
+            .iter()
+            .map(|segment| segment.shape.ray_crossings(pt))
+            .sum();
+        crossings % 2 == 1
+    }
+
+    /// Returns a point that lies on the contour
+    pub fn point_on_contour(&self) -> Option<Vector3<f64>> {
+        self.segments
+            .first()
+            .map(|segment| segment.shape.midpoint().0)
+    }
+
+    /// Area enclosed by a closed contour. It is positive if the contour goes around counter-clockwise, and negative
+    /// if it goes around clockwise.
+    pub fn signed_area(&self) -> f64 {
+        self.segments
+            .iter()
+            .map(|segment| segment.shape.signed_area())
+            .sum()
+    }
+
+    /// Distances smaller than this are rounding error. How far apart two entities can be while still being joined
+    /// up is set by `OffsetOptions::join_tolerance` instead.
+    pub(crate) const EPSILON: f64 = 1e-6;
+
+    /// Joins two open contours that have ends within `tolerance` of each other into one contour. The joined contour
+    /// is closed if its other two ends are within `tolerance` of each other too.
+    ///
+    /// Gives back both contours (boxed, as they are fairly large) if they cannot be joined.
+    pub fn combine_attempt(self, other: Self, tolerance: f64) -> Result<Self, Box<(Self, Self)>> {
         match (self.end_points, other.end_points) {
             (Some((a, b)), Some((c, d))) => {
                 let ac_dist = (a - c).magnitude();
@@ -138,200 +363,195 @@ impl Contour {
                 let bc_dist = (b - c).magnitude();
                 let bd_dist = (b - d).magnitude();
 
-                let mut new_entities =
-                    Vec::with_capacity(self.entities.len() + other.entities.len());
-                if ac_dist < Self::EPSILON {
+                let (first, second, end_points, closing_dist) = if ac_dist < tolerance {
                     // our beginnning attaches to their beginning
                     // reverse ourself, so our beginning becomes our end
-                    new_entities.extend(self.entities.into_iter().rev());
-                    new_entities.extend(other.entities);
-
-                    Ok(Self {
-                        entities: new_entities,
-                        end_points: if bd_dist >= Self::EPSILON {
-                            Some((b, d))
-                        } else {
-                            None
-                        },
-                    })
-                } else if ad_dist < Self::EPSILON {
+                    (self.reversed(), other, (b, d), bd_dist)
+                } else if ad_dist < tolerance {
                     // their beginning - their end - our beginning - our end
-                    new_entities.extend(other.entities);
-                    new_entities.extend(self.entities);
-
-                    Ok(Self {
-                        entities: new_entities,
-                        end_points: if bc_dist >= Self::EPSILON {
-                            Some((c, b))
-                        } else {
-                            None
-                        }, // use their beginning, our end
-                    })
-                } else if bc_dist < Self::EPSILON {
+                    (other, self, (c, b), bc_dist)
+                } else if bc_dist < tolerance {
                     // our end attaches to their beginning
-                    new_entities.extend(self.entities);
-                    new_entities.extend(other.entities);
-                    Ok(Self {
-                        entities: new_entities,
-                        end_points: if ad_dist >= Self::EPSILON {
-                            Some((a, d))
-                        } else {
-                            None
-                        },
-                    })
-                } else if bd_dist < Self::EPSILON {
+                    (self, other, (a, d), ad_dist)
+                } else if bd_dist < tolerance {
                     // our end attaches to their end
                     // reverse them, so their beginning attaches to our end
-                    new_entities.extend(self.entities);
-                    new_entities.extend(other.entities.into_iter().rev());
-
-                    Ok(Self {
-                        entities: new_entities,
-                        end_points: if ac_dist >= Self::EPSILON {
-                            Some((a, c))
-                        } else {
-                            None
-                        },
-                    })
+                    (self, other.reversed(), (a, c), ac_dist)
                 } else {
-                    Err((self, other))
-                }
+                    return Err(Box::new((self, other)));
+                };
+
+                let mut segments = first.segments;
+                segments.extend(second.segments);
+                Ok(Self {
+                    segments,
+                    end_points: if closing_dist >= tolerance {
+                        Some(end_points)
+                    } else {
+                        None
+                    },
+                })
             }
-            _ => Err((self, other)), // if one of the contours is closed, cannot combine with it
+            _ => Err(Box::new((self, other))), // if one of the contours is closed, cannot combine with it
         }
     }
 
     /// Negative amount will shrink the area of the contour. Positive amount will grow the area of the contour.
-    pub fn offset_contour(&self, amount: f64) -> Result<Self, KerfAdjustmentError> {
+    ///
+    /// Corners where the offset entities move apart from each other are filled in according to `options.corner_join`.
+    /// The join tolerance is used as a distance in drawing units whatever `options.join_tolerance_mode` says, so a
+    /// tolerance that is relative to the drawing has to be worked out with `OffsetOptions::for_extents` first.
+    pub fn offset_contour(
+        &self,
+        amount: f64,
+        options: &OffsetOptions,
+    ) -> Result<OffsetContours, KerfAdjustmentError> {
         if self.is_open() {
             return Err(KerfAdjustmentError {
                 reason: KerfAdjustmentErrorReason::CannotOffsetOpenContour,
+                entity: self
+                    .segments
+                    .first()
+                    .map(|segment| Box::new(segment.source.common.clone())),
             });
         }
 
-        let mut entities_iterator = self.entities.clone().into_iter();
-
-        let first_entity = entities_iterator.next().ok_or(KerfAdjustmentError {
-            reason: KerfAdjustmentErrorReason::CannotOffsetEmptyContour,
-        })?;
-
-        // Create a contour and put the first entity (offset, of) into it
-        let mut result_contour: Contour = naive_entity_offset(first_entity.clone(), amount)
-            .ok_or(KerfAdjustmentError {
-                reason: KerfAdjustmentErrorReason::CannotOffsetEntity(first_entity.specific),
-            })?
-            .0 // TODO: handle both the inside and outside contours
-            .into();
-
-        for entity in entities_iterator {
-            // Offset the entity, get 2 offset versions
-            let (offset_a, offset_b) =
-                naive_entity_offset(entity.clone(), amount).ok_or(KerfAdjustmentError {
-                    reason: KerfAdjustmentErrorReason::CannotOffsetEntity(entity.specific),
-                })?;
-
-            // Try to connect one or the other to the
-            let maybe_combined_offset_contour_into_result = result_contour
-                .combine_attempt(offset_a.into())
-                .or_else(|(result_contour, _)| result_contour.combine_attempt(offset_b.into()));
-
-            match maybe_combined_offset_contour_into_result {
-                Ok(success) => result_contour = success,
-                Err(_) => {
-                    return Err(KerfAdjustmentError {
-                        reason: KerfAdjustmentErrorReason::CannotConnectContourAfterAdjustment,
-                    })
-                }
+        offset_closed_contour(self, amount, options)
+    }
+
+    /// Replaces an open contour with a closed outline around it, offset by `distance` on both sides. `options` are used
+    /// in the same way as for `offset_contour`.
+    pub fn outline_contour(
+        &self,
+        distance: f64,
+        options: &OffsetOptions,
+    ) -> Result<OffsetContours, KerfAdjustmentError> {
+        outline_open_contour(self, distance, options)
+    }
+
+    /// The entities that the contour is made of, as they were, in the order they first show up in it. An entity that
+    /// makes more than one segment (like a polyline) is only given back once.
+    pub fn source_entities(&self) -> Vec<Entity> {
+        let mut sources: Vec<&Rc<Entity>> = Vec::new();
+        for segment in &self.segments {
+            if !sources.iter().any(|source| Rc::ptr_eq(source, &segment.source)) {
+                sources.push(&segment.source);
             }
         }
-
-        return Ok(result_contour);
+        sources.into_iter().map(|source| Entity::clone(source)).collect()
     }
-}
 
-/// Returns either
-///     - A pair of entites that are offset_amount away from the provided entity
-///     - None, if the entity cannot be offset
-fn naive_entity_offset(entity: Entity, amount: f64) -> Option<(Entity, Entity)> {
-    let offset_entity_specifics: (EntityType, EntityType) = match entity.specific {
-        EntityType::Circle(circle) => (
-            EntityType::Circle(Circle {
-                radius: circle.radius + amount,
-                ..circle.clone()
-            }),
-            EntityType::Circle(Circle {
-                radius: circle.radius - amount,
-                ..circle
-            }),
-        ),
-        EntityType::Arc(arc) => (
-            EntityType::Arc(Arc {
-                radius: arc.radius + amount,
-                ..arc.clone()
-            }),
-            EntityType::Arc(Arc {
-                radius: arc.radius - amount,
-                ..arc
-            }),
-        ),
-        EntityType::Line(line) => {
-            let VectorWrapper(start_vec) = line.p1.clone().into();
-            let VectorWrapper(end_vec) = line.p2.clone().into();
-            let VectorWrapper(plane_normal) = line.extrusion_direction.clone().into();
-            let delta = end_vec - start_vec;
-            // The plane normal should be [0, 0, 1] & delta should be [x, y, 0] --> the perp delta should be [-y, x, 0]
-            let mut perpendicular_delta = delta.cross(&plane_normal);
-            perpendicular_delta.set_magnitude(amount);
-
-            let (new_start_1, new_end_1) = (
-                VectorWrapper(start_vec + perpendicular_delta),
-                VectorWrapper(end_vec + perpendicular_delta),
-            );
+    /// Where the contour starts and ends, if it is open
+    pub fn open_path(&self) -> Option<OpenPath> {
+        let (start, end) = self.end_points?;
+        let segment = self.segments.first()?;
+        Some(OpenPath::new(&segment.source.common, &start, &end))
+    }
 
-            let (new_start_2, new_end_2) = (
-                VectorWrapper(start_vec - perpendicular_delta),
-                VectorWrapper(end_vec - perpendicular_delta),
-            );
+    /// Turns the contour back into entities.
+    ///
+    /// Segments that came from a polyline are written back out as a polyline. A closed contour with a polyline in it
+    /// is written out as a single closed polyline, so that it stays in one piece, as long as all of its entities are on
+    /// the same layer in the same colour. Every other segment becomes a line, arc or circle of its own, and keeps the
+    /// layer and colour of the entity it came from.
+    pub fn to_entities(&self) -> Vec<Entity> {
+        let polyline_of = |segment: &ContourSegment| match &segment.source.specific {
+            EntityType::LwPolyline(poly) => Some((poly.clone(), segment.source.common.clone())),
+            _ => None,
+        };
+        let shapes_of = |segments: &[ContourSegment]| {
+            segments
+                .iter()
+                .map(|segment| segment.shape.clone())
+                .collect::<Vec<_>>()
+        };
+        let has_circle = self
+            .segments
+            .iter()
+            .any(|segment| matches!(segment.shape, Segment::Circle { .. }));
+
+        // the colour of each entity is kept, so a closed polyline is only made if nothing would be drawn differently
+        let looks_the_same = |common: &EntityCommon| {
+            self.segments.iter().all(|segment| {
+                let other = &segment.source.common;
+                other.layer == common.layer
+                    && other.color == common.color
+                    && other.color_24_bit == common.color_24_bit
+                    && other.line_type_name == common.line_type_name
+            })
+        };
+        if !self.is_open() && !has_circle {
+            if let Some((template, common)) = self
+                .segments
+                .iter()
+                .find_map(polyline_of)
+                .filter(|(_, common)| looks_the_same(common))
+            {
+                let poly = lwpolyline_along(&template, &shapes_of(&self.segments), true);
+                return vec![Entity {
+                    common,
+                    specific: EntityType::LwPolyline(poly),
+                }];
+            }
+        }
 
-            (
-                EntityType::Line(Line {
-                    p1: new_start_1.into(),
-                    p2: new_end_1.into(),
-                    ..line.clone()
-                }),
-                EntityType::Line(Line {
-                    p1: new_start_2.into(),
-                    p2: new_end_2.into(),
-                    ..line
-                }),
-            )
+        let mut entities = Vec::with_capacity(self.segments.len());
+        let mut rest = self.segments.as_slice();
+        while let Some(segment) = rest.first() {
+            match polyline_of(segment) {
+                Some((template, common)) => {
+                    let run = rest
+                        .iter()
+                        .take_while(|other| Rc::ptr_eq(&other.source, &segment.source))
+                        .count();
+                    let poly = lwpolyline_along(&template, &shapes_of(&rest[..run]), false);
+                    entities.push(Entity {
+                        common,
+                        specific: EntityType::LwPolyline(poly),
+                    });
+                    rest = &rest[run..];
+                }
+                None => {
+                    entities.push(segment.to_entity());
+                    rest = &rest[1..];
+                }
+            }
         }
-        _ => return None,
-    };
+        entities
+    }
+}
 
-    Some((
-        Entity {
-            common: entity.common.clone(),
-            specific: offset_entity_specifics.0,
-        },
-        Entity {
-            common: entity.common,
-            specific: offset_entity_specifics.1,
-        },
-    ))
+/// The result of offsetting a contour
+pub struct OffsetContours {
+    /// Offsetting can split a contour into several contours where a narrow part of it gets pinched off, or make it
+    /// disappear entirely if it is too small
+    pub contours: Vec<Contour>,
+    /// Entities that mark where a contour disappeared, if it was asked for (like a point at the center of a circle
+    /// that collapsed)
+    pub markers: Vec<Entity>,
+    /// Parts of the original contour that are too small to be left in the offset contour
+    pub disappeared: Vec<DisappearedFeature>,
+    /// Problems with the offset that did not stop it from being made
+    pub warnings: Vec<OffsetWarning>,
 }
 
 pub trait ContourVecToDxf {
     fn to_dxf(self) -> Drawing;
+
+    /// Replaces the entities of `drawing` with the entities of the contours. Everything else in the drawing (the
+    /// header, tables, blocks and objects) is kept as it is.
+    ///
+    /// Each entity keeps the handle of the entity it was made from, so several of them can have the same handle.
+    fn to_dxf_in(self, drawing: Drawing) -> Drawing;
 }
 
 impl ContourVecToDxf for Vec<Contour> {
     fn to_dxf(self) -> dxf::Drawing {
-        let mut drawing: Drawing = Default::default();
+        self.to_dxf_in(Default::default())
+    }
 
-        for contour in self {
-            drawing.entities.extend(contour.entities);
-        }
+    fn to_dxf_in(self, mut drawing: Drawing) -> Drawing {
+        drawing.entities = self.iter().flat_map(Contour::to_entities).collect();
         drawing
     }
 }
@@ -339,8 +559,8 @@ impl ContourVecToDxf for Vec<Contour> {
 #[cfg(test)]
 mod contour_test {
 
-    use crate::contour::find_endpoints_of_entity;
-    use crate::contour::Contour;
+    use crate::contour::{find_endpoints_of_entity, Contour, EntityEnds};
+    use crate::errors::KerfAdjustmentErrorReason;
     use dxf::entities::*;
     use dxf::Point;
     use dxf::Vector;
@@ -360,24 +580,18 @@ mod contour_test {
     }
 
     fn check_contour_is_sequential(c: &Contour) -> Option<()> {
-        let endpoints = c.entities.iter().map(find_endpoints_of_entity).try_fold(
-            Vec::new(),
-            |mut acc, val| {
-                acc.push(val?);
-                Some(acc)
-            },
-        )?;
-
-        let mut endpoints_iter = endpoints.into_iter();
-        let mut prev_end = endpoints_iter.next()?.1;
-
-        for (next_start, next_end) in endpoints_iter {
-            if (prev_end - next_start).magnitude() > EPSILON {
+        let (start, end) = c.end_points?;
+        let mut at = start;
+        for segment in c.segments.iter() {
+            if (segment.shape.start() - at).magnitude() > EPSILON {
                 return None;
             }
-            prev_end = next_end;
+            at = segment.shape.end();
         }
 
+        if (at - end).magnitude() > EPSILON {
+            return None;
+        }
         Some(())
     }
 
@@ -390,10 +604,10 @@ mod contour_test {
 
         // when: we connect them in a contour
         let contour_12 = Contour::from(line1.clone())
-            .combine_attempt(line2.clone().into())
+            .combine_attempt(line2.clone().into(), Contour::EPSILON)
             .unwrap();
         let contour_21 = Contour::from(line2.clone())
-            .combine_attempt(line1.clone().into())
+            .combine_attempt(line1.clone().into(), Contour::EPSILON)
             .unwrap();
 
         // then: it passes the test
@@ -408,7 +622,9 @@ mod contour_test {
         let line2 = line_between(Point::origin(), Point::new(-3., 1., 0.));
 
         // when: we combine them into a contour
-        let combined_entity = Contour::from(line1).combine_attempt(line2.into()).unwrap();
+        let combined_entity = Contour::from(line1)
+            .combine_attempt(line2.into(), Contour::EPSILON)
+            .unwrap();
 
         // then: sequential entities
         check_contour_is_sequential(&combined_entity).unwrap();
@@ -421,7 +637,9 @@ mod contour_test {
         let line2 = line_between(Point::new(-3., 1., 0.), Point::origin());
 
         // when: we combine them into a contour
-        let combined_entity = Contour::from(line1).combine_attempt(line2.into()).unwrap();
+        let combined_entity = Contour::from(line1)
+            .combine_attempt(line2.into(), Contour::EPSILON)
+            .unwrap();
 
         // then: sequential entities
         check_contour_is_sequential(&combined_entity).unwrap();
@@ -434,7 +652,9 @@ mod contour_test {
         let line2 = line_between(Point::origin(), Point::new(-3., 1., 0.));
 
         // when: we combine them into a contour
-        let combined_entity = Contour::from(line1).combine_attempt(line2.into()).unwrap();
+        let combined_entity = Contour::from(line1)
+            .combine_attempt(line2.into(), Contour::EPSILON)
+            .unwrap();
 
         // then: sequential entities
         check_contour_is_sequential(&combined_entity).unwrap();
@@ -447,9 +667,155 @@ mod contour_test {
         let line2 = line_between(Point::origin(), Point::new(-3., 1., 0.));
 
         // when: we combine them into a contour
-        let combined_entity = Contour::from(line1).combine_attempt(line2.into()).unwrap();
+        let combined_entity = Contour::from(line1)
+            .combine_attempt(line2.into(), Contour::EPSILON)
+            .unwrap();
 
         // then: sequential entities
         check_contour_is_sequential(&combined_entity).unwrap();
     }
+
+    #[test]
+    pub fn test_reversed_arc_goes_the_other_way() {
+        // given: a quarter circle going counter-clockwise from (1, 0) to (0, 1), and a line that starts where it does
+        let arc = Entity::new(EntityType::Arc(Arc::new(Point::origin(), 1., 0., 90.)));
+        let line = line_between(Point::new(1., 0., 0.), Point::new(1., -1., 0.));
+
+        // when: we combine them into a contour, which means walking the arc backwards
+        let combined = Contour::from(arc)
+            .combine_attempt(line.into(), Contour::EPSILON)
+            .unwrap();
+
+        // then: the arc is walked clockwise, straight into the line
+        check_contour_is_sequential(&combined).unwrap();
+        assert!(combined.segments[0].shape.bulge() < 0.);
+
+        // and: it is still written out as the same counter-clockwise arc
+        match &combined.to_entities()[0].specific {
+            EntityType::Arc(arc) => {
+                assert!(arc.start_angle.abs() < EPSILON);
+                assert!((arc.end_angle - 90.).abs() < EPSILON);
+            }
+            _ => panic!("first entity should be the arc"),
+        }
+    }
+
+    #[test]
+    pub fn test_signed_area_follows_winding() {
+        // given: a 10 by 2 slot with rounded ends, going around counter-clockwise
+        let vertex = |x, y, bulge| dxf::LwPolylineVertex {
+            x,
+            y,
+            bulge,
+            ..Default::default()
+        };
+        let mut slot = LwPolyline {
+            vertices: vec![
+                vertex(0., 0., 0.),
+                vertex(10., 0., 1.),
+                vertex(10., 2., 0.),
+                vertex(0., 2., 1.),
+            ],
+            ..Default::default()
+        };
+        slot.set_is_closed(true);
+        let slot = Contour::from(Entity::new(EntityType::LwPolyline(slot)));
+
+        // when: we work out its area going both ways around
+        let area = slot.signed_area();
+        let reversed_area = slot.reversed().signed_area();
+
+        // then: it is the rectangle plus a circle, positive counter-clockwise and negative clockwise
+        let expected = 20. + std::f64::consts::PI;
+        assert!((area - expected).abs() < EPSILON);
+        assert!((reversed_area + expected).abs() < EPSILON);
+    }
+
+    #[test]
+    pub fn test_entities_are_classified() {
+        // given: a circle, some text, an arc that is tilted out of the XY plane, a multiline, a line that climbs out of
+        // the XY plane, a line that is raised off of it, a spline that climbs out of it, and a spline whose normal is
+        // tilted
+        let circle = Entity::new(EntityType::Circle(Circle::new(Point::origin(), 1.)));
+        let text = Entity::new(EntityType::Text(Text::default()));
+        let tilted_arc = Entity::new(EntityType::Arc(Arc {
+            radius: 1.,
+            normal: Vector::new(1., 0., 1.),
+            ..Default::default()
+        }));
+        let multiline = Entity::new(EntityType::MLine(MLine::default()));
+        let climbing_line = Entity::new(EntityType::Line(Line::new(
+            Point::new(0., 0., 0.),
+            Point::new(1., 0., 1.),
+        )));
+        let raised_line = Entity::new(EntityType::Line(Line::new(
+            Point::new(0., 0., 2.),
+            Point::new(1., 0., 2.),
+        )));
+        let straight_spline = |end: Point, normal: Vector| {
+            let mut spline = Spline {
+                degree_of_curve: 1,
+                knot_values: vec![0., 0., 1., 1.],
+                normal,
+                ..Default::default()
+            };
+            spline.control_points.push(Point::origin());
+            spline.control_points.push(end);
+            spline
+        };
+        let twisted_spline = straight_spline(Point::new(1., 1., 1.), Vector::z_axis());
+        let tilted_spline = straight_spline(Point::new(1., 0., 0.), Vector::y_axis());
+        let twisted_spline = Entity::new(EntityType::Spline(twisted_spline));
+        let tilted_spline = Entity::new(EntityType::Spline(tilted_spline));
+
+        // when: we work out what each of them is
+        let circle = find_endpoints_of_entity(&circle);
+        let text = find_endpoints_of_entity(&text);
+        let tilted_arc = find_endpoints_of_entity(&tilted_arc);
+        let multiline = find_endpoints_of_entity(&multiline);
+
+        let climbing_line = find_endpoints_of_entity(&climbing_line);
+        let raised_line = find_endpoints_of_entity(&raised_line);
+        let twisted_spline = find_endpoints_of_entity(&twisted_spline);
+        let tilted_spline = find_endpoints_of_entity(&tilted_spline);
+
+        // then: the circle is closed, the raised line is open, the text is not cut, and the others cannot be cut
+        assert_eq!(circle.unwrap(), EntityEnds::Closed);
+        assert_eq!(text.unwrap(), EntityEnds::PassThrough);
+        for entity in [tilted_arc, climbing_line, twisted_spline, tilted_spline] {
+            assert!(matches!(
+                entity.unwrap_err().reason,
+                KerfAdjustmentErrorReason::ThreeDimensionalEntity
+            ));
+        }
+        assert!(matches!(raised_line.unwrap(), EntityEnds::Open(..)));
+        assert!(matches!(
+            multiline.unwrap_err().reason,
+            KerfAdjustmentErrorReason::UnsupportedEntity(specific) if matches!(*specific, EntityType::MLine(_))
+        ));
+    }
+
+    #[test]
+    pub fn test_spline_with_only_fit_points_is_unsupported() {
+        // given: a spline that goes through fit points, but has no control points
+        let mut spline = Spline {
+            degree_of_curve: 3,
+            ..Default::default()
+        };
+        for x in 0..4 {
+            spline.fit_points.push(Point::new(x as f64, (x * x) as f64, 0.));
+        }
+        let mut spline = Entity::new(EntityType::Spline(spline));
+        spline.common.handle = 0x2A;
+
+        // when: we work out what it is
+        let error = find_endpoints_of_entity(&spline).unwrap_err();
+
+        // then: it cannot be cut, and the error names the spline
+        assert!(matches!(
+            &error.reason,
+            KerfAdjustmentErrorReason::UnsupportedEntity(specific) if matches!(**specific, EntityType::Spline(_))
+        ));
+        assert_eq!(error.handle(), Some(0x2A));
+    }
 }
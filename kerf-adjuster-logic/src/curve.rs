@@ -0,0 +1,110 @@
+//! Approximation of smooth curves (like splines and ellipses) as a chain of straight lines, so that they can be joined
+//! and offset like any other entity.
+use crate::contour::Contour;
+use crate::geometry::distance_to_segment;
+use dxf::entities::*;
+use dxf::LwPolylineVertex;
+use nalgebra::Vector3;
+
+/// How many points along each piece of the approximation are compared against the chord that replaces it. The curve
+/// is only measured at these points, so a wiggle that fits in between two of them can stray further than measured.
+const DEVIATION_SAMPLES: usize = 8;
+
+/// How many times a span can be cut in half while looking for a chord that is close enough to the curve
+const MAX_SUBDIVISIONS: usize = 16;
+
+/// A chain of straight lines that follows a curve
+pub struct CurveApproximation {
+    pub points: Vec<Vector3<f64>>,
+    /// The furthest that the curve strays from the chain of lines
+    pub max_deviation: f64,
+}
+
+/// How far the curve strays from the straight line between its points at `from` and `to`
+fn chord_deviation(curve: &dyn Fn(f64) -> Vector3<f64>, from: f64, to: f64) -> f64 {
+    let (a, b) = (curve(from), curve(to));
+    (1..DEVIATION_SAMPLES)
+        .map(|i| {
+            let t = from + (to - from) * (i as f64) / (DEVIATION_SAMPLES as f64);
+            distance_to_segment(&curve(t), &a, &b)
+        })
+        .fold(0., f64::max)
+}
+
+/// Adds the end points of chords that follow the curve from `from` to `to` within `tolerance` to `points`.
+/// Returns the largest deviation of any of those chords from the curve.
+fn approximate_span(
+    curve: &dyn Fn(f64) -> Vector3<f64>,
+    from: f64,
+    to: f64,
+    tolerance: f64,
+    depth: usize,
+    points: &mut Vec<Vector3<f64>>,
+) -> f64 {
+    let deviation = chord_deviation(curve, from, to);
+    if deviation <= tolerance || depth >= MAX_SUBDIVISIONS {
+        points.push(curve(to));
+        return deviation;
+    }
+
+    let mid = (from + to) / 2.;
+    let first_half = approximate_span(curve, from, mid, tolerance, depth + 1, points);
+    let second_half = approximate_span(curve, mid, to, tolerance, depth + 1, points);
+    first_half.max(second_half)
+}
+
+/// Approximates a curve with straight lines that stay within `tolerance` of it
+///
+/// The deviation is measured by sampling the curve rather than worked out exactly, so it is only guaranteed at the
+/// sampled points. This is close enough for the smooth curves that splines and ellipses make, as long as each chord
+/// does not cover a wiggle in the curve. A chord is also kept as it is once a span has been cut in half
+/// `MAX_SUBDIVISIONS` times, whatever its deviation is; `max_deviation` says how far it strays in that case.
+///
+/// `curve` gives the point on the curve for each parameter value, and `boundaries` are the parameter values where
+/// the curve starts and ends, along with the places in between where the curve is allowed to have a kink.
+pub fn approximate_curve(
+    curve: &dyn Fn(f64) -> Vector3<f64>,
+    boundaries: &[f64],
+    tolerance: f64,
+) -> CurveApproximation {
+    let mut points = boundaries.first().map(|&t| curve(t)).into_iter().collect();
+    let max_deviation = boundaries
+        .windows(2)
+        .map(|span| approximate_span(curve, span[0], span[1], tolerance, 0, &mut points))
+        .fold(0., f64::max);
+
+    CurveApproximation {
+        points,
+        max_deviation,
+    }
+}
+
+/// Builds a polyline that follows the approximation of a curve. The polyline is closed if the curve ends where it
+/// starts.
+pub fn approximation_to_lwpolyline(approximation: &CurveApproximation) -> LwPolyline {
+    let mut points = approximation.points.clone();
+    let is_closed = match (points.first(), points.last()) {
+        (Some(first), Some(last)) => {
+            points.len() > 2 && (first - last).magnitude() < Contour::EPSILON
+        }
+        _ => false,
+    };
+    if is_closed {
+        // The closing segment comes from the closed flag instead
+        points.pop();
+    }
+
+    let mut poly = LwPolyline {
+        vertices: points
+            .into_iter()
+            .map(|pt| LwPolylineVertex {
+                x: pt.x,
+                y: pt.y,
+                ..Default::default()
+            })
+            .collect(),
+        ..Default::default()
+    };
+    poly.set_is_closed(is_closed);
+    poly
+}
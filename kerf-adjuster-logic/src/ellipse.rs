@@ -0,0 +1,138 @@
+//! Evaluation of ellipses and elliptical arcs
+//!
+//! The offset of an ellipse is not an ellipse, so ellipses are approximated by straight lines which can then be
+//! offset like any other entity.
+use crate::contour::{Contour, VectorWrapper};
+use crate::curve::{approximate_curve, CurveApproximation};
+use dxf::entities::*;
+use nalgebra::Vector3;
+use std::f64::consts::{FRAC_PI_2, PI};
+
+/// Point on an ellipse at the given parameter (in radians)
+///
+/// The parameter is not the angle of the point as seen from the center. It goes around the ellipse the way the angle
+/// goes around a circle that has been squashed along the minor axis.
+fn point_on_ellipse(ellipse: &Ellipse, parameter: f64) -> Vector3<f64> {
+    let VectorWrapper(center) = ellipse.center.clone().into();
+    let VectorWrapper(major_axis) = ellipse.major_axis.clone().into();
+    let VectorWrapper(normal) = ellipse.normal.clone().into();
+
+    // The minor axis is a quarter turn counter-clockwise from the major axis, as seen looking down the normal
+    let minor_axis =
+        normal.cross(&major_axis).normalize() * major_axis.magnitude() * ellipse.minor_axis_ratio;
+
+    center + major_axis * parameter.cos() + minor_axis * parameter.sin()
+}
+
+/// How far around the ellipse the entity goes, in radians
+fn parameter_sweep(ellipse: &Ellipse) -> f64 {
+    let sweep = (ellipse.end_parameter - ellipse.start_parameter).rem_euclid(2. * PI);
+    if sweep < 1e-9 {
+        2. * PI
+    } else {
+        sweep
+    }
+}
+
+/// Whether the entity goes all the way around the ellipse
+pub(crate) fn is_full_ellipse(ellipse: &Ellipse) -> bool {
+    (parameter_sweep(ellipse) - 2. * PI).abs() < 1e-9
+}
+
+fn is_valid_ellipse(ellipse: &Ellipse) -> bool {
+    let VectorWrapper(major_axis) = ellipse.major_axis.clone().into();
+    let VectorWrapper(normal) = ellipse.normal.clone().into();
+    major_axis.magnitude() > Contour::EPSILON
+        && normal.cross(&major_axis).magnitude() > 0.
+        && ellipse.minor_axis_ratio > 0.
+}
+
+/// First and last point of an elliptical arc, or `None` for a full ellipse
+pub fn ellipse_endpoints(ellipse: &Ellipse) -> Option<(Vector3<f64>, Vector3<f64>)> {
+    if is_full_ellipse(ellipse) || !is_valid_ellipse(ellipse) {
+        return None;
+    }
+
+    Some((
+        point_on_ellipse(ellipse, ellipse.start_parameter),
+        point_on_ellipse(ellipse, ellipse.end_parameter),
+    ))
+}
+
+/// Approximates an ellipse or elliptical arc with straight lines that stay within `tolerance` of it
+///
+/// Returns `None` if the ellipse has no size.
+pub fn approximate_ellipse(ellipse: &Ellipse, tolerance: f64) -> Option<CurveApproximation> {
+    if !is_valid_ellipse(ellipse) {
+        return None;
+    }
+
+    // Start off with at most a quarter of the ellipse in each span, so that no span ends where it starts
+    let sweep = parameter_sweep(ellipse);
+    let span_count = (sweep / FRAC_PI_2).ceil().max(1.) as usize;
+    let boundaries = (0..=span_count)
+        .map(|i| ellipse.start_parameter + sweep * (i as f64) / (span_count as f64))
+        .collect::<Vec<_>>();
+
+    Some(approximate_curve(
+        &|t| point_on_ellipse(ellipse, t),
+        &boundaries,
+        tolerance,
+    ))
+}
+
+#[cfg(test)]
+mod ellipse_test {
+    use crate::ellipse::{approximate_ellipse, ellipse_endpoints};
+    use dxf::entities::*;
+    use dxf::{Point, Vector};
+    use nalgebra::Vector3;
+    use std::f64::consts::PI;
+
+    const EPSILON: f64 = 1e-9;
+
+    /// An ellipse centered on (1, 1) that is 4 wide along the y axis, and 2 wide along the x axis
+    fn upright_ellipse(start_parameter: f64, end_parameter: f64) -> Ellipse {
+        Ellipse {
+            center: Point::new(1., 1., 0.),
+            major_axis: Vector::new(0., 2., 0.),
+            minor_axis_ratio: 0.5,
+            start_parameter,
+            end_parameter,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn test_elliptical_arc_endpoints() {
+        // given: the half of the ellipse that is on the left of its center
+        let ellipse = upright_ellipse(0., PI);
+
+        // when: we find its endpoints
+        let (start, end) = ellipse_endpoints(&ellipse).unwrap();
+
+        // then: it goes from the top of the ellipse to the bottom of it, and a full ellipse has no endpoints
+        assert!((start - Vector3::new(1., 3., 0.)).magnitude() < EPSILON);
+        assert!((end - Vector3::new(1., -1., 0.)).magnitude() < EPSILON);
+        assert!(ellipse_endpoints(&upright_ellipse(0., 2. * PI)).is_none());
+    }
+
+    #[test]
+    pub fn test_full_ellipse_approximation() {
+        // given: a full ellipse
+        let ellipse = upright_ellipse(0., 2. * PI);
+
+        // when: we approximate it
+        let approximation = approximate_ellipse(&ellipse, 0.01).unwrap();
+
+        // then: it ends where it starts, stays within the tolerance, and every corner is on the ellipse
+        let first = approximation.points.first().unwrap();
+        let last = approximation.points.last().unwrap();
+        assert!((first - last).magnitude() < EPSILON);
+        assert!(approximation.max_deviation > 0. && approximation.max_deviation <= 0.01);
+        for pt in approximation.points.iter() {
+            let (x, y) = (pt.x - 1., pt.y - 1.);
+            assert!((x * x + y * y / 4. - 1.).abs() < EPSILON);
+        }
+    }
+}
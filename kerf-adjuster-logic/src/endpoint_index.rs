@@ -0,0 +1,55 @@
+//! A spatial hash of contour end points, for finding the ends that are close to a point without checking every end
+//! in the drawing
+use nalgebra::Vector3;
+use std::collections::HashMap;
+
+/// Which square of the grid a point is in
+type Cell = (i64, i64);
+
+/// End points that have been put into square cells, keyed by the cell they are in
+pub struct EndpointIndex {
+    cell_size: f64,
+    cells: HashMap<Cell, Vec<(usize, Vector3<f64>)>>,
+}
+
+impl EndpointIndex {
+    /// Makes an empty index that can find points that are up to `cell_size` away from each other
+    pub fn new(cell_size: f64) -> Self {
+        Self {
+            cell_size,
+            cells: HashMap::new(),
+        }
+    }
+
+    fn cell_of(&self, pt: &Vector3<f64>) -> Cell {
+        (
+            (pt.x / self.cell_size).floor() as i64,
+            (pt.y / self.cell_size).floor() as i64,
+        )
+    }
+
+    /// Adds a point, which belongs to whatever `id` is
+    pub fn insert(&mut self, id: usize, pt: Vector3<f64>) {
+        let cell = self.cell_of(&pt);
+        self.cells.entry(cell).or_default().push((id, pt));
+    }
+
+    /// The points that are no further than `distance` from `pt`, along with what they belong to.
+    ///
+    /// `distance` should be no bigger than the cell size, since only the cells next to the one `pt` is in are
+    /// searched.
+    pub fn near(
+        &self,
+        pt: &Vector3<f64>,
+        distance: f64,
+    ) -> impl Iterator<Item = (usize, Vector3<f64>)> + '_ {
+        let (x, y) = self.cell_of(pt);
+        let pt = *pt;
+        (-1..=1)
+            .flat_map(move |dx| (-1..=1).map(move |dy| (x + dx, y + dy)))
+            .filter_map(move |cell| self.cells.get(&cell))
+            .flatten()
+            .filter(move |(_, other)| (other - pt).magnitude() <= distance)
+            .copied()
+    }
+}
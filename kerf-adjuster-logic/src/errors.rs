@@ -1,6 +1,9 @@
-use dxf::entities::EntityType;
+use crate::report::OpenPath;
+use dxf::entities::{EntityCommon, EntityType};
+use wasm_bindgen::prelude::*;
 
-fn entity_type_name(e: &EntityType) -> String {
+/// Name of the kind of entity (e.g. `Line`)
+pub(crate) fn entity_type_name(e: &EntityType) -> String {
     format!("{:?}", e)
         .split("(")
         .next()
@@ -10,12 +13,47 @@ fn entity_type_name(e: &EntityType) -> String {
 
 #[derive(Debug)]
 pub enum KerfAdjustmentErrorReason {
-    UnsupportedEntity(EntityType),
+    UnsupportedEntity(Box<EntityType>),
     ThreeDimensionalEntity,
     CannotOffsetOpenContour,
     CannotOffsetEmptyContour,
     CannotConnectContourAfterAdjustment,
-    CannotOffsetEntity(EntityType),
+    CannotOffsetEntity(Box<EntityType>),
+    /// The file could not be read as a DXF file
+    InvalidDxf(String),
+    /// The offset drawing could not be written out as a DXF file
+    CannotWriteDxf(String),
+    /// The kerf was given in a unit, but the drawing does not say what units it is in
+    UnspecifiedDrawingUnits,
+    /// An INSERT places a block that is not in the drawing
+    MissingBlock(String),
+    /// A block contains an INSERT that places the block itself (possibly through other blocks)
+    RecursiveBlock(String),
+    /// The drawing has contours whose ends do not meet, and open contours are refused
+    OpenContours(Vec<OpenPath>),
+    /// Two contours whose ends meet could not be joined into one
+    CannotJoinContours,
+}
+
+impl KerfAdjustmentErrorReason {
+    /// Name of the kind of error, so that it can be told apart from other errors without parsing the message
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::UnsupportedEntity(_) => "UnsupportedEntity",
+            Self::ThreeDimensionalEntity => "ThreeDimensionalEntity",
+            Self::CannotOffsetOpenContour => "CannotOffsetOpenContour",
+            Self::CannotOffsetEmptyContour => "CannotOffsetEmptyContour",
+            Self::CannotConnectContourAfterAdjustment => "CannotConnectContourAfterAdjustment",
+            Self::CannotOffsetEntity(_) => "CannotOffsetEntity",
+            Self::InvalidDxf(_) => "InvalidDxf",
+            Self::CannotWriteDxf(_) => "CannotWriteDxf",
+            Self::UnspecifiedDrawingUnits => "UnspecifiedDrawingUnits",
+            Self::MissingBlock(_) => "MissingBlock",
+            Self::RecursiveBlock(_) => "RecursiveBlock",
+            Self::OpenContours(_) => "OpenContours",
+            Self::CannotJoinContours => "CannotJoinContours",
+        }
+    }
 }
 
 impl std::fmt::Display for KerfAdjustmentErrorReason {
@@ -50,18 +88,105 @@ impl std::fmt::Display for KerfAdjustmentErrorReason {
                     entity_type_name(entity_type)
                 )
             }
+            Self::InvalidDxf(problem) => write!(fmt, "Could not read the DXF file: {}", problem),
+            Self::CannotWriteDxf(problem) => {
+                write!(fmt, "Could not write the offset DXF file: {}", problem)
+            }
+            Self::UnspecifiedDrawingUnits => write!(
+                fmt,
+                "The drawing does not say what units it is in ($INSUNITS is not set), so the kerf cannot be converted into drawing units"
+            ),
+            Self::MissingBlock(name) => {
+                write!(fmt, "Block reference to a block that does not exist (block name: {})", name)
+            }
+            Self::RecursiveBlock(name) => {
+                write!(fmt, "Block is placed inside of itself (block name: {})", name)
+            }
+            Self::OpenContours(paths) => {
+                let paths = paths.iter().map(|path| path.to_string()).collect::<Vec<_>>();
+                write!(
+                    fmt,
+                    "Drawing has contours whose ends do not meet, so it cannot be offset: {}",
+                    paths.join("; ")
+                )
+            }
+            Self::CannotJoinContours => write!(
+                fmt,
+                "Found contours whose ends meet, but could not join them into one contour"
+            ),
         }
     }
 }
 
-#[derive(Debug)]
+/// An error from kerf adjusting a drawing. In JavaScript, this is the exception that gets thrown.
+#[wasm_bindgen]
 pub struct KerfAdjustmentError {
+    #[wasm_bindgen(skip)]
     pub reason: KerfAdjustmentErrorReason,
+    /// The entity that caused the error, if it was caused by one. It is boxed to keep `Result`s that carry the error
+    /// small.
+    #[wasm_bindgen(skip)]
+    pub entity: Option<Box<EntityCommon>>,
+}
+
+impl From<KerfAdjustmentErrorReason> for KerfAdjustmentError {
+    fn from(reason: KerfAdjustmentErrorReason) -> Self {
+        Self {
+            reason,
+            entity: None,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl KerfAdjustmentError {
+    /// Name of the kind of error (e.g. `UnsupportedEntity`)
+    #[wasm_bindgen(getter)]
+    pub fn kind(&self) -> String {
+        self.reason.kind().to_string()
+    }
+
+    /// Description of the error, to show to the user
+    #[wasm_bindgen(getter)]
+    pub fn message(&self) -> String {
+        self.to_string()
+    }
+
+    /// Handle of the entity that caused the error
+    #[wasm_bindgen(getter)]
+    pub fn handle(&self) -> Option<u32> {
+        self.entity.as_ref().map(|common| common.handle)
+    }
+
+    /// Layer of the entity that caused the error
+    #[wasm_bindgen(getter)]
+    pub fn layer(&self) -> Option<String> {
+        self.entity.as_ref().map(|common| common.layer.clone())
+    }
+}
+
+// `EntityCommon` is not `Debug`, so only the parts of it that identify the entity are shown
+impl std::fmt::Debug for KerfAdjustmentError {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        fmt.debug_struct("KerfAdjustmentError")
+            .field("reason", &self.reason)
+            .field("handle", &self.handle())
+            .field("layer", &self.layer())
+            .finish()
+    }
 }
 
 impl std::fmt::Display for KerfAdjustmentError {
     fn fmt(&self, fmt: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
-        write!(fmt, "Kerf Adjustment Error: {}", self.reason)
+        write!(fmt, "Kerf Adjustment Error: {}", self.reason)?;
+        if let Some(common) = &self.entity {
+            write!(
+                fmt,
+                " (entity handle: {:X}, layer: {})",
+                common.handle, common.layer
+            )?;
+        }
+        Ok(())
     }
 }
 
@@ -0,0 +1,181 @@
+//! Plane geometry helpers. Everything here works in the XY plane and ignores the z coordinate.
+use nalgebra::Vector3;
+
+/// z component of the cross product of two vectors in the XY plane
+///
+/// Positive if `b` points counter-clockwise of `a`, negative if it points clockwise of it.
+pub fn cross_z(a: &Vector3<f64>, b: &Vector3<f64>) -> f64 {
+    a.x * b.y - a.y * b.x
+}
+
+/// Rotates a vector in the XY plane by 90 degrees counter-clockwise
+pub fn left_normal(v: &Vector3<f64>) -> Vector3<f64> {
+    Vector3::new(-v.y, v.x, 0.)
+}
+
+/// Point on a circle at the given angle (in radians, counter-clockwise from the x axis)
+pub fn point_on_circle(center: &Vector3<f64>, radius: f64, angle: f64) -> Vector3<f64> {
+    center + Vector3::new(angle.cos(), angle.sin(), 0.) * radius
+}
+
+/// Angle (in radians, counter-clockwise from the x axis) of a point as seen from the center of a circle
+pub fn angle_on_circle(center: &Vector3<f64>, pt: &Vector3<f64>) -> f64 {
+    (pt.y - center.y).atan2(pt.x - center.x)
+}
+
+/// Distance from a point to the closest point on the line segment between `a` and `b`
+pub fn distance_to_segment(pt: &Vector3<f64>, a: &Vector3<f64>, b: &Vector3<f64>) -> f64 {
+    let ab = b - a;
+    let length_sq = ab.magnitude_squared();
+    if length_sq == 0. {
+        return (pt - a).magnitude();
+    }
+    let t = ((pt - a).dot(&ab) / length_sq).clamp(0., 1.);
+    (pt - (a + ab * t)).magnitude()
+}
+
+/// Intersection of the infinite line through `p` with direction `d` and the infinite line through `q` with
+/// direction `e`. Returns `None` if the lines are parallel.
+pub fn line_line_intersection(
+    p: &Vector3<f64>,
+    d: &Vector3<f64>,
+    q: &Vector3<f64>,
+    e: &Vector3<f64>,
+) -> Option<Vector3<f64>> {
+    let denominator = cross_z(d, e);
+    if denominator.abs() < 1e-12 * d.magnitude() * e.magnitude() {
+        return None;
+    }
+    let t = cross_z(&(q - p), e) / denominator;
+    Some(p + d * t)
+}
+
+/// Intersections of the infinite line through `p` with direction `d` and a circle
+pub fn line_circle_intersections(
+    p: &Vector3<f64>,
+    d: &Vector3<f64>,
+    center: &Vector3<f64>,
+    radius: f64,
+) -> Vec<Vector3<f64>> {
+    let d_unit = d.normalize();
+    // Closest point on the line to the center of the circle
+    let closest = p + d_unit * (center - p).dot(&d_unit);
+    let dist_sq = (closest - center).magnitude_squared();
+    if dist_sq > radius * radius {
+        return vec![];
+    }
+    let half_chord = (radius * radius - dist_sq).sqrt();
+    vec![closest - d_unit * half_chord, closest + d_unit * half_chord]
+}
+
+/// Intersections of two circles
+pub fn circle_circle_intersections(
+    center_a: &Vector3<f64>,
+    radius_a: f64,
+    center_b: &Vector3<f64>,
+    radius_b: f64,
+) -> Vec<Vector3<f64>> {
+    let between = center_b - center_a;
+    let dist = between.magnitude();
+    if dist < 1e-12 || dist > radius_a + radius_b || dist < (radius_a - radius_b).abs() {
+        return vec![];
+    }
+    // Distance from the center of `a` to the chord between the intersection points
+    let along = (dist * dist + radius_a * radius_a - radius_b * radius_b) / (2. * dist);
+    let half_chord = (radius_a * radius_a - along * along).max(0.).sqrt();
+    let chord_center = center_a + between * (along / dist);
+    let across = left_normal(&between) * (half_chord / dist);
+    vec![chord_center + across, chord_center - across]
+}
+
+/// Center and radius of the arc described by a polyline bulge between two points
+///
+/// The bulge is the tangent of a quarter of the angle the arc sweeps through. A positive bulge goes
+/// counter-clockwise from `start` to `end`, and a negative one goes clockwise.
+pub fn bulge_to_arc(start: &Vector3<f64>, end: &Vector3<f64>, bulge: f64) -> (Vector3<f64>, f64) {
+    let chord = end - start;
+    let chord_length = chord.magnitude();
+    let radius = chord_length * (1. + bulge * bulge) / (4. * bulge.abs());
+    let center_from_midpoint = (1. - bulge * bulge) / (4. * bulge);
+    let center = (start + end) / 2. + left_normal(&chord) * center_from_midpoint;
+    (center, radius)
+}
+
+/// The bulge of an arc that sweeps through `sweep` radians, going counter-clockwise if `ccw` is true
+pub fn arc_to_bulge(sweep: f64, ccw: bool) -> f64 {
+    let bulge = (sweep / 4.).tan();
+    if ccw {
+        bulge
+    } else {
+        -bulge
+    }
+}
+
+#[cfg(test)]
+mod geometry_test {
+    use crate::geometry::*;
+    use nalgebra::Vector3;
+
+    const EPSILON: f64 = 1e-9;
+
+    #[test]
+    pub fn test_line_line_intersection() {
+        // given: a horizontal line and a vertical line
+        let (p, d) = (Vector3::new(0., 1., 0.), Vector3::new(1., 0., 0.));
+        let (q, e) = (Vector3::new(3., 0., 0.), Vector3::new(0., -2., 0.));
+
+        // when: we intersect them
+        let pt = line_line_intersection(&p, &d, &q, &e).unwrap();
+
+        // then: they meet where we expect, and parallel lines do not meet
+        assert!((pt - Vector3::new(3., 1., 0.)).magnitude() < EPSILON);
+        assert!(line_line_intersection(&p, &d, &q, &d).is_none());
+    }
+
+    #[test]
+    pub fn test_circle_intersections() {
+        // given: two unit circles that are 1 unit apart, and a line through both of their centers
+        let (a, b) = (Vector3::new(0., 0., 0.), Vector3::new(1., 0., 0.));
+
+        // when: we intersect them
+        let circle_pts = circle_circle_intersections(&a, 1., &b, 1.);
+        let line_pts = line_circle_intersections(&a, &(b - a), &b, 1.);
+
+        // then: every intersection is on both curves
+        assert_eq!(circle_pts.len(), 2);
+        for pt in circle_pts {
+            assert!(((pt - a).magnitude() - 1.).abs() < EPSILON);
+            assert!(((pt - b).magnitude() - 1.).abs() < EPSILON);
+        }
+        assert_eq!(line_pts.len(), 2);
+        assert!((line_pts[0] - Vector3::new(0., 0., 0.)).magnitude() < EPSILON);
+        assert!((line_pts[1] - Vector3::new(2., 0., 0.)).magnitude() < EPSILON);
+    }
+
+    #[test]
+    pub fn test_bulge_to_arc() {
+        // given: the two ends of a semicircle
+        let (start, end) = (Vector3::new(1., 0., 0.), Vector3::new(-1., 0., 0.));
+
+        // when: we find the arc for a counter-clockwise and a clockwise half circle between them
+        let (ccw_center, ccw_radius) = bulge_to_arc(&start, &end, 1.);
+        let (cw_center, cw_radius) = bulge_to_arc(&start, &end, -1.);
+        let (quarter_center, quarter_radius) = bulge_to_arc(
+            &start,
+            &Vector3::new(0., 1., 0.),
+            arc_to_bulge(std::f64::consts::FRAC_PI_2, true),
+        );
+
+        // then: all of them are centered on the origin
+        for (center, radius) in [
+            (ccw_center, ccw_radius),
+            (cw_center, cw_radius),
+            (quarter_center, quarter_radius),
+        ]
+        .iter()
+        {
+            assert!(center.magnitude() < EPSILON);
+            assert!((radius - 1.).abs() < EPSILON);
+        }
+    }
+}
@@ -0,0 +1,370 @@
+//! Extraction of contours from the parts of a drawing where more than two entities meet at a point
+//!
+//! Joining entities end to end only works when each end meets exactly one other end. Where three or more ends meet
+//! (at a T-junction, a shared edge, or a construction line running across a part), there is more than one way to
+//! carry on from there, and which one gets picked would depend on the order of the entities. Those parts of the
+//! drawing are treated as a planar graph instead, whose smallest closed faces become the contours.
+use crate::contour::Contour;
+use crate::endpoint_index::EndpointIndex;
+use crate::segment::Segment;
+use crate::report::Junction;
+use nalgebra::Vector3;
+
+/// An open contour, running from one node of the graph to another
+struct Edge {
+    contour: Contour,
+    /// The node at the first end point of the contour, and the node at the second
+    nodes: [usize; 2],
+}
+
+/// An edge, walked in one of its two directions
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct HalfEdge {
+    edge: usize,
+    /// Whether the edge is walked from its first end point to its second
+    forward: bool,
+}
+
+impl HalfEdge {
+    fn twin(self) -> Self {
+        HalfEdge {
+            forward: !self.forward,
+            ..self
+        }
+    }
+
+    fn start_node(self, edges: &[Edge]) -> usize {
+        edges[self.edge].nodes[if self.forward { 0 } else { 1 }]
+    }
+
+    fn end_node(self, edges: &[Edge]) -> usize {
+        self.twin().start_node(edges)
+    }
+}
+
+/// What came out of looking for faces in the drawing
+pub struct ExtractedContours {
+    /// Closed contours around the smallest faces of the graph
+    pub faces: Vec<Contour>,
+    /// Contours that still need to be joined up end to end, because they are not near a junction or they are not on
+    /// the edge of any face
+    pub remaining: Vec<Contour>,
+    /// Points where more than two entities meet, in order of their position
+    pub junctions: Vec<Junction>,
+}
+
+/// The segments of an open contour, in the order they are walked from its first end point to its second (or the
+/// other way around if `forward` is false)
+fn walked_pieces(contour: &Contour, forward: bool) -> Vec<Segment> {
+    let shapes = contour.segments.iter().map(|segment| segment.shape.clone());
+    if forward {
+        shapes.collect()
+    } else {
+        shapes.rev().map(Segment::reversed).collect()
+    }
+}
+
+/// Direction (as an angle counter-clockwise from the x axis) that a half edge sets off in from its first node
+fn departure_angle(edges: &[Edge], half_edge: HalfEdge) -> f64 {
+    let contour = &edges[half_edge.edge].contour;
+    match walked_pieces(contour, half_edge.forward).first() {
+        Some(piece) => {
+            let tangent = piece.start_tangent();
+            tangent.y.atan2(tangent.x)
+        }
+        None => 0.,
+    }
+}
+
+/// Signed area enclosed by a loop of half edges. It is positive if the loop goes counter-clockwise.
+fn signed_area(edges: &[Edge], face: &[HalfEdge]) -> f64 {
+    face.iter()
+        .flat_map(|half_edge| walked_pieces(&edges[half_edge.edge].contour, half_edge.forward))
+        .map(|piece| piece.signed_area())
+        .sum()
+}
+
+/// Finds the node that has a point within `tolerance` of `pt`, or adds a new node there if there is none
+fn node_at(
+    pt: Vector3<f64>,
+    nodes: &mut Vec<Vector3<f64>>,
+    index: &mut EndpointIndex,
+    tolerance: f64,
+) -> usize {
+    let existing = index
+        .near(&pt, tolerance)
+        .filter(|(_, other)| (other - pt).magnitude() < tolerance)
+        .map(|(node, _)| node)
+        .min();
+    match existing {
+        Some(node) => node,
+        None => {
+            nodes.push(pt);
+            index.insert(nodes.len() - 1, pt);
+            nodes.len() - 1
+        }
+    }
+}
+
+fn root(parents: &mut [usize], mut node: usize) -> usize {
+    while parents[node] != node {
+        parents[node] = parents[parents[node]];
+        node = parents[node];
+    }
+    node
+}
+
+/// Walks around the faces of the graph made by the `alive` edges. Every half edge is on exactly one face, which is
+/// to its left.
+fn trace_faces(edges: &[Edge], alive: &[bool], node_count: usize) -> Vec<Vec<HalfEdge>> {
+    let half_edges = (0..edges.len())
+        .filter(|&edge| alive[edge])
+        .flat_map(|edge| {
+            vec![
+                HalfEdge {
+                    edge,
+                    forward: true,
+                },
+                HalfEdge {
+                    edge,
+                    forward: false,
+                },
+            ]
+        })
+        .collect::<Vec<_>>();
+
+    // The half edges leaving each node, going counter-clockwise around it
+    let mut leaving = vec![Vec::new(); node_count];
+    for &half_edge in half_edges.iter() {
+        leaving[half_edge.start_node(edges)].push((departure_angle(edges, half_edge), half_edge));
+    }
+    for around in leaving.iter_mut() {
+        around.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+    }
+
+    // Arriving at a node, the face on our left carries on along the first half edge clockwise from the way we came
+    let next = |half_edge: HalfEdge| {
+        let around = &leaving[half_edge.end_node(edges)];
+        let back = around
+            .iter()
+            .position(|(_, h)| *h == half_edge.twin())
+            .unwrap();
+        around[(back + around.len() - 1) % around.len()].1
+    };
+
+    let mut visited = std::collections::HashSet::new();
+    let mut faces = Vec::new();
+    for &first in half_edges.iter() {
+        if visited.contains(&first) {
+            continue;
+        }
+        let mut face = Vec::new();
+        let mut half_edge = first;
+        while visited.insert(half_edge) {
+            face.push(half_edge);
+            half_edge = next(half_edge);
+        }
+        faces.push(face);
+    }
+    faces
+}
+
+/// Turns a loop of half edges into a closed contour
+fn face_to_contour(edges: &[Edge], face: &[HalfEdge]) -> Contour {
+    let segments = face
+        .iter()
+        .flat_map(|half_edge| {
+            let contour = edges[half_edge.edge].contour.clone();
+            if half_edge.forward {
+                contour.segments
+            } else {
+                contour.reversed().segments
+            }
+        })
+        .collect();
+    Contour {
+        segments,
+        end_points: None,
+    }
+}
+
+/// Turns the parts of the drawing where more than two open contours meet at a point into closed contours around
+/// the smallest faces they make. An open contour that is between two faces ends up in both of them.
+///
+/// Parts of the drawing without junctions are left to be joined up end to end, as are dangling contours and contours
+/// that are not on the edge of any face.
+pub fn extract_faces(contours: Vec<Contour>, tolerance: f64) -> ExtractedContours {
+    let mut nodes = Vec::new();
+    let mut index = EndpointIndex::new(tolerance);
+    let mut edges = Vec::with_capacity(contours.len());
+    let mut remaining = Vec::new();
+    for contour in contours {
+        match contour.end_points {
+            Some((a, b)) => {
+                let start = node_at(a, &mut nodes, &mut index, tolerance);
+                let end = node_at(b, &mut nodes, &mut index, tolerance);
+                edges.push(Edge {
+                    contour,
+                    nodes: [start, end],
+                });
+            }
+            None => remaining.push(contour),
+        }
+    }
+
+    let mut degrees = vec![0; nodes.len()];
+    let mut parents = (0..nodes.len()).collect::<Vec<_>>();
+    for edge in edges.iter() {
+        let [a, b] = edge.nodes;
+        degrees[a] += 1;
+        degrees[b] += 1;
+        let (root_a, root_b) = (root(&mut parents, a), root(&mut parents, b));
+        parents[root_a] = root_b;
+    }
+
+    let mut junctions = (0..nodes.len())
+        .filter(|&node| degrees[node] > 2)
+        .map(|node| Junction::new(&nodes[node], degrees[node]))
+        .collect::<Vec<_>>();
+    junctions.sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+
+    // Only the parts of the drawing that have a junction somewhere in them are turned into faces
+    let mut has_junction = vec![false; nodes.len()];
+    for (node, _) in degrees.iter().enumerate().filter(|(_, &degree)| degree > 2) {
+        let node_root = root(&mut parents, node);
+        has_junction[node_root] = true;
+    }
+    let mut alive = edges
+        .iter()
+        .map(|edge| has_junction[root(&mut parents, edge.nodes[0])])
+        .collect::<Vec<_>>();
+
+    // Dangling contours cannot be on the edge of a face, so they are trimmed off until there are none left
+    let mut attached = vec![Vec::new(); nodes.len()];
+    for (i, edge) in edges.iter().enumerate().filter(|(i, _)| alive[*i]) {
+        attached[edge.nodes[0]].push(i);
+        attached[edge.nodes[1]].push(i);
+    }
+    let alive_degree =
+        |node: usize, alive: &[bool]| attached[node].iter().filter(|&&e| alive[e]).count();
+    let mut dangling = (0..nodes.len())
+        .filter(|&node| alive_degree(node, &alive) == 1)
+        .collect::<Vec<_>>();
+    while let Some(node) = dangling.pop() {
+        let edge = match attached[node].iter().copied().find(|&e| alive[e]) {
+            Some(edge) => edge,
+            None => continue,
+        };
+        alive[edge] = false;
+        for &end in edges[edge].nodes.iter() {
+            if alive_degree(end, &alive) == 1 {
+                dangling.push(end);
+            }
+        }
+    }
+
+    // Faces that go counter-clockwise are inside the part of the graph that they are in. The face that goes clockwise
+    // is the outside of that part. Faces that go along both sides of a contour do not make a closed loop.
+    let faces = trace_faces(&edges, &alive, nodes.len())
+        .into_iter()
+        .filter(|face| {
+            face.iter()
+                .all(|half_edge| !face.contains(&half_edge.twin()))
+        })
+        .filter(|face| signed_area(&edges, face) > 0.)
+        .collect::<Vec<_>>();
+
+    let mut on_face = vec![false; edges.len()];
+    for half_edge in faces.iter().flatten() {
+        on_face[half_edge.edge] = true;
+    }
+    let face_contours = faces
+        .iter()
+        .map(|face| face_to_contour(&edges, face))
+        .collect();
+
+    remaining.extend(
+        edges
+            .drain(..)
+            .zip(on_face)
+            .filter(|(_, on_face)| !on_face)
+            .map(|(edge, _)| edge.contour),
+    );
+
+    ExtractedContours {
+        faces: face_contours,
+        remaining,
+        junctions,
+    }
+}
+
+#[cfg(test)]
+mod graph_test {
+    use crate::contour::Contour;
+    use crate::graph::extract_faces;
+    use dxf::entities::*;
+    use dxf::Point;
+
+    fn line(x1: f64, y1: f64, x2: f64, y2: f64) -> Contour {
+        Contour::from(Entity::new(EntityType::Line(Line::new(
+            Point::new(x1, y1, 0.),
+            Point::new(x2, y2, 0.),
+        ))))
+    }
+
+    #[test]
+    pub fn test_divided_rectangle_makes_two_faces() {
+        // given: a 20x10 rectangle with a line down the middle of it, in two different orders
+        let lines = vec![
+            line(0., 0., 10., 0.),
+            line(10., 0., 20., 0.),
+            line(20., 0., 20., 10.),
+            line(20., 10., 10., 10.),
+            line(10., 10., 0., 10.),
+            line(0., 10., 0., 0.),
+            line(10., 0., 10., 10.),
+        ];
+        let reversed = lines.iter().rev().cloned().collect::<Vec<_>>();
+
+        for contours in [lines, reversed] {
+            // when: we look for faces
+            let extracted = extract_faces(contours, 1e-6);
+
+            // then: each half of the rectangle is its own closed contour, and nothing is left over
+            assert_eq!(extracted.faces.len(), 2);
+            assert!(extracted.faces.iter().all(|face| face.segments.len() == 4));
+            assert!(extracted.remaining.is_empty());
+
+            // and: the ends of the middle line are reported as junctions, in the same order either way
+            let junctions = extracted
+                .junctions
+                .iter()
+                .map(|j| (j.x, j.y, j.ends))
+                .collect::<Vec<_>>();
+            assert_eq!(junctions, vec![(10., 0., 3), (10., 10., 3)]);
+        }
+    }
+
+    #[test]
+    pub fn test_dangling_line_is_left_over() {
+        // given: a square with a line sticking into it from one side, and a separate open line
+        let contours = vec![
+            line(0., 0., 10., 0.),
+            line(10., 0., 10., 10.),
+            line(10., 10., 0., 10.),
+            line(0., 10., 0., 5.),
+            line(0., 5., 0., 0.),
+            line(0., 5., 5., 5.),
+            line(20., 0., 30., 0.),
+        ];
+
+        // when: we look for faces
+        let extracted = extract_faces(contours, 1e-6);
+
+        // then: the square is a face, and the lines that are not part of it still need joining up
+        assert_eq!(extracted.faces.len(), 1);
+        assert_eq!(extracted.faces[0].segments.len(), 5);
+        assert_eq!(extracted.remaining.len(), 2);
+        assert_eq!(extracted.junctions.len(), 1);
+    }
+}
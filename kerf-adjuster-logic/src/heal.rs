@@ -0,0 +1,255 @@
+//! Repair of small gaps between entities that are meant to meet
+//!
+//! Some programs export drawings where the ends of neighbouring entities are further apart than the join tolerance,
+//! which leaves the contour open so that it never gets offset. Gaps that are no bigger than the heal distance are
+//! closed up before the contours are joined, and each of them is reported so that the repair can be checked.
+use crate::contour::{Contour, VectorWrapper};
+use crate::endpoint_index::EndpointIndex;
+use crate::options::GapRepair;
+use crate::report::HealedGap;
+use crate::segment::{ContourSegment, Segment};
+use dxf::entities::*;
+use nalgebra::Vector3;
+
+/// One of the two ends of an open contour
+#[derive(Clone, Copy)]
+struct End {
+    contour: usize,
+    /// Whether this is the second of the contour's end points
+    is_last: bool,
+}
+
+/// Moves the start (or the end, if `is_last`) of a segment over to `to`.
+///
+/// Lines just get longer or shorter. Arcs from polylines keep their bulge, as if the vertex of the polyline had been
+/// moved. Returns false for other arcs, which would stop being the arc they were in the drawing.
+fn move_end_of_segment(segment: &mut ContourSegment, is_last: bool, to: &Vector3<f64>) -> bool {
+    let (start, end) = if is_last {
+        (segment.shape.start(), *to)
+    } else {
+        (*to, segment.shape.end())
+    };
+    let is_from_polyline = matches!(
+        segment.source.specific,
+        EntityType::LwPolyline(_) | EntityType::Polyline(_)
+    );
+    segment.shape = match segment.shape {
+        Segment::Line { .. } => Segment::Line { start, end },
+        Segment::Arc { .. } if is_from_polyline => {
+            Segment::from_bulge(start, end, segment.shape.bulge())
+        }
+        _ => return false,
+    };
+    true
+}
+
+/// Moves one end of an open contour over to `to`. Returns false if the segment at that end cannot be moved.
+fn move_end_of_contour(contour: &mut Contour, is_last: bool, to: &Vector3<f64>) -> bool {
+    let (first, last) = match contour.end_points {
+        Some(end_points) => end_points,
+        None => return false,
+    };
+    let segment = if is_last {
+        contour.segments.last_mut()
+    } else {
+        contour.segments.first_mut()
+    };
+    if !segment.is_some_and(|segment| move_end_of_segment(segment, is_last, to)) {
+        return false;
+    }
+
+    contour.end_points = if is_last {
+        Some((first, *to))
+    } else {
+        Some((*to, last))
+    };
+    true
+}
+
+/// Moves both ends over to `to`, leaving the contours as they were if either of the ends cannot be moved
+fn snap_ends(contours: &mut [Contour], a: End, b: End, to: &Vector3<f64>) -> bool {
+    let mut first = contours[a.contour].clone();
+    if !move_end_of_contour(&mut first, a.is_last, to) {
+        return false;
+    }
+
+    if a.contour == b.contour {
+        if !move_end_of_contour(&mut first, b.is_last, to) {
+            return false;
+        }
+        // Both ends of the contour are in the same place now, so it is closed
+        first.end_points = None;
+    } else {
+        let mut second = contours[b.contour].clone();
+        if !move_end_of_contour(&mut second, b.is_last, to) {
+            return false;
+        }
+        contours[b.contour] = second;
+    }
+    contours[a.contour] = first;
+    true
+}
+
+/// A line across the gap between `a` and `b`, drawn like the end of the contour that it starts from (the end of the
+/// contour if `is_last`, otherwise its start). The bridge can end up as the first segment of the contour it joins up,
+/// so it takes the layer and colour that decide the kerf as well.
+fn bridge(from: &Contour, is_last: bool, a: &Vector3<f64>, b: &Vector3<f64>) -> Contour {
+    let segment = if is_last {
+        from.segments.last()
+    } else {
+        from.segments.first()
+    };
+    let mut common = segment.map_or_else(EntityCommon::default, |segment| segment.source.common.clone());
+    // the bridge is not in the drawing yet, so it is given a handle of its own when the drawing is saved
+    common.handle = 0;
+    Contour::create_from_entity(Entity {
+        common,
+        specific: EntityType::Line(Line::new(
+            VectorWrapper(*a).into(),
+            VectorWrapper(*b).into(),
+        )),
+    })
+}
+
+/// Closes up gaps between the ends of open contours that are at least `tolerance` but no more than `heal_distance`
+/// apart, so that the contours can be joined afterwards.
+///
+/// The closest ends are paired up first, and each end is only paired up once. Ends that are already within
+/// `tolerance` of another end are left alone, since they get joined up anyway.
+pub fn heal_gaps(
+    mut contours: Vec<Contour>,
+    heal_distance: f64,
+    tolerance: f64,
+    repair: GapRepair,
+) -> (Vec<Contour>, Vec<HealedGap>) {
+    let ends = contours
+        .iter()
+        .enumerate()
+        .filter_map(|(i, c)| c.end_points.map(|end_points| (i, end_points)))
+        .flat_map(|(contour, (first, last))| {
+            vec![
+                (
+                    End {
+                        contour,
+                        is_last: false,
+                    },
+                    first,
+                ),
+                (
+                    End {
+                        contour,
+                        is_last: true,
+                    },
+                    last,
+                ),
+            ]
+        })
+        .collect::<Vec<_>>();
+    let mut index = EndpointIndex::new(heal_distance.max(tolerance));
+    for (i, (_, pt)) in ends.iter().enumerate() {
+        index.insert(i, *pt);
+    }
+
+    let mut is_paired = ends
+        .iter()
+        .enumerate()
+        .map(|(i, (_, pt))| {
+            index
+                .near(pt, tolerance)
+                .any(|(j, other)| j != i && (other - pt).magnitude() < tolerance)
+        })
+        .collect::<Vec<_>>();
+
+    let mut gaps = Vec::new();
+    for (i, (end_i, pt)) in ends.iter().enumerate() {
+        for (j, other) in index.near(pt, heal_distance) {
+            // Closing up a single line onto itself would not make a shape
+            let is_single_line = end_i.contour == ends[j].0.contour
+                && matches!(
+                    contours[end_i.contour].segments.as_slice(),
+                    [ContourSegment {
+                        shape: Segment::Line { .. },
+                        ..
+                    }]
+                );
+
+            let size = (other - pt).magnitude();
+            if j > i && size >= tolerance && !is_single_line {
+                gaps.push((size, i, j));
+            }
+        }
+    }
+    gaps.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+
+    let mut healed = Vec::new();
+    let mut bridges = Vec::new();
+    for (_, i, j) in gaps {
+        if is_paired[i] || is_paired[j] {
+            continue;
+        }
+        is_paired[i] = true;
+        is_paired[j] = true;
+
+        let ((end_a, a), (end_b, b)) = (ends[i], ends[j]);
+        let middle = (a + b) / 2.;
+        let used_repair =
+            if repair == GapRepair::Snap && snap_ends(&mut contours, end_a, end_b, &middle) {
+                GapRepair::Snap
+            } else {
+                bridges.push(bridge(&contours[end_a.contour], end_a.is_last, &a, &b));
+                GapRepair::Bridge
+            };
+        healed.push(HealedGap::new(&a, &b, used_repair));
+    }
+
+    contours.extend(bridges);
+    (contours, healed)
+}
+
+#[cfg(test)]
+mod heal_test {
+    use crate::contour::Contour;
+    use crate::heal::heal_gaps;
+    use crate::options::GapRepair;
+    use dxf::entities::*;
+    use dxf::Point;
+
+    fn line(x1: f64, y1: f64, x2: f64, y2: f64) -> Contour {
+        Contour::from(Entity::new(EntityType::Line(Line::new(
+            Point::new(x1, y1, 0.),
+            Point::new(x2, y2, 0.),
+        ))))
+    }
+
+    #[test]
+    pub fn test_gaps_are_snapped_or_bridged() {
+        // given: two lines with a small gap between them, and an arc with a smaller gap between it and the first line
+        let arc = Contour::from(Entity::new(EntityType::Arc(Arc::new(
+            Point::new(5., 0., 0.),
+            5.,
+            180.,
+            360.,
+        ))));
+        let contours = vec![line(0., 0.01, 0., 10.), line(0., 10.02, 10., 10.), arc];
+
+        // when: we heal the gaps, snapping them where we can
+        let (healed, gaps) = heal_gaps(contours, 0.1, 1e-6, GapRepair::Snap);
+
+        // then: both gaps are reported, smallest first, and the arc's gap is bridged since arcs cannot be moved
+        assert_eq!(gaps.len(), 2);
+        assert!((gaps[0].size - 0.01).abs() < 1e-9);
+        assert_eq!(gaps[0].repair, GapRepair::Bridge);
+        assert!((gaps[1].size - 0.02).abs() < 1e-9);
+        assert_eq!(gaps[1].repair, GapRepair::Snap);
+        assert!(gaps[1].x.abs() < 1e-9 && (gaps[1].y - 10.01).abs() < 1e-9);
+
+        // and: the lines are moved to meet in the middle of their gap, and a line is added across the other gap
+        assert_eq!(healed.len(), 4);
+        let (_, first_end) = healed[0].end_points.unwrap();
+        let (second_start, _) = healed[1].end_points.unwrap();
+        assert!((first_end - second_start).magnitude() < 1e-9);
+        assert!((first_end.y - 10.01).abs() < 1e-9);
+        let (bridge_start, bridge_end) = healed[3].end_points.unwrap();
+        assert!(((bridge_start - bridge_end).magnitude() - 0.01).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,587 @@
+//! Expansion of block references (INSERT entities) into the entities that they stand for
+//!
+//! Repeated parts are often drawn once in a block, and placed with INSERT entities that can move, scale, rotate and
+//! mirror the block, and repeat it in rows and columns (MINSERT). Each copy of the block is turned into entities in
+//! world coordinates, so that they can be made into contours like any other entity.
+//!
+//! The copies stay separate entities in the offset drawing. They are not put back into blocks, since offsetting
+//! can give copies of the same block different shapes (for example where one copy is placed inside of a part, and so
+//! is offset as a hole).
+//!
+//! Entities in a block that cannot be moved into place, like dimensions, hatches and proxy entities, are copied into
+//! the drawing as they are in the block, so that nothing in the block is lost.
+use crate::contour::{find_endpoints_of_entity, VectorWrapper};
+use crate::ellipse::is_full_ellipse;
+use crate::errors::{KerfAdjustmentError, KerfAdjustmentErrorReason};
+use crate::ocs::{arbitrary_axes, entity_to_wcs, is_flat};
+use crate::polyline::{explode_lwpolyline, explode_polyline, is_2d_polyline};
+use dxf::entities::*;
+use dxf::{Block, Point, Vector};
+use nalgebra::{Matrix2, Rotation2, Vector2};
+use std::f64::consts::PI;
+
+/// How far apart (relative to their size) the scale along two directions can be while still counting as the same
+const UNIFORM_SCALE_TOLERANCE: f64 = 1e-9;
+
+/// An affine map of the XY plane, `pt -> matrix * pt + offset`. Heights are left as they are.
+#[derive(Clone, Copy, Debug)]
+struct Transform {
+    matrix: Matrix2<f64>,
+    offset: Vector2<f64>,
+}
+
+impl Transform {
+    fn identity() -> Self {
+        Self {
+            matrix: Matrix2::identity(),
+            offset: Vector2::zeros(),
+        }
+    }
+
+    /// Where `insert` puts the copy of the block in the given column and row of its array
+    fn of_insert(insert: &Insert, base_point: &Point, column: i16, row: i16) -> Self {
+        // The insert is placed in its own object coordinate system
+        let VectorWrapper(normal) = insert.extrusion_direction.clone().into();
+        let (x_axis, y_axis) = arbitrary_axes(&normal);
+        let to_world = Matrix2::new(x_axis.x, y_axis.x, x_axis.y, y_axis.y);
+
+        let rotation = Rotation2::new(insert.rotation.to_radians()).into_inner();
+        let scale = Matrix2::new(insert.x_scale_factor, 0., 0., insert.y_scale_factor);
+        // Rows and columns are spaced out along the rotated axes, and are not scaled
+        let array_offset = Vector2::new(
+            f64::from(column) * insert.column_spacing,
+            f64::from(row) * insert.row_spacing,
+        );
+        let base_point = Vector2::new(base_point.x, base_point.y);
+        let location = Vector2::new(insert.location.x, insert.location.y);
+
+        Self {
+            matrix: to_world * rotation * scale,
+            offset: to_world * (location + rotation * (array_offset - scale * base_point)),
+        }
+    }
+
+    /// This transform followed by `outer`
+    fn then(&self, outer: &Transform) -> Self {
+        Self {
+            matrix: outer.matrix * self.matrix,
+            offset: outer.matrix * self.offset + outer.offset,
+        }
+    }
+
+    fn point(&self, pt: &Point) -> Point {
+        let moved = self.matrix * Vector2::new(pt.x, pt.y) + self.offset;
+        Point::new(moved.x, moved.y, pt.z)
+    }
+
+    /// Moves a direction, which is not affected by the offset
+    fn direction(&self, x: f64, y: f64) -> Vector2<f64> {
+        self.matrix * Vector2::new(x, y)
+    }
+
+    /// The angle (in degrees) that a direction at `degrees` ends up at
+    fn angle(&self, degrees: f64) -> f64 {
+        let radians = degrees.to_radians();
+        let moved = self.direction(radians.cos(), radians.sin());
+        moved.y.atan2(moved.x).to_degrees().rem_euclid(360.)
+    }
+
+    /// Whether the transform turns counter-clockwise shapes clockwise
+    fn is_mirrored(&self) -> bool {
+        self.matrix.determinant() < 0.
+    }
+
+    /// How much lengths are scaled by, if they are scaled the same in every direction. Circles stay circles under
+    /// a transform like that.
+    fn uniform_scale(&self) -> Option<f64> {
+        let (a, b) = (self.matrix.column(0), self.matrix.column(1));
+        let (a_length, b_length) = (a.norm(), b.norm());
+        let size = a_length.max(b_length);
+        if (a_length - b_length).abs() <= UNIFORM_SCALE_TOLERANCE * size
+            && a.dot(&b).abs() <= UNIFORM_SCALE_TOLERANCE * size * size
+        {
+            Some(a_length)
+        } else {
+            None
+        }
+    }
+}
+
+/// Moves an ellipse (or elliptical arc), which comes out as another ellipse
+fn transform_ellipse(ellipse: &Ellipse, t: &Transform) -> Ellipse {
+    let VectorWrapper(normal) = ellipse.normal.clone().into();
+    let VectorWrapper(major) = ellipse.major_axis.clone().into();
+    let minor = normal.normalize().cross(&major) * ellipse.minor_axis_ratio;
+
+    // The moved ellipse goes through `center + conjugate * (cos(p), sin(p))` for each parameter p. Its axes are the
+    // directions that `conjugate` stretches the most and the least.
+    let conjugate = t.matrix * Matrix2::new(major.x, minor.x, major.y, minor.y);
+    let stretch = conjugate * conjugate.transpose();
+    let axis_angle = 0.5 * (2. * stretch[(0, 1)]).atan2(stretch[(0, 0)] - stretch[(1, 1)]);
+    let major_direction = Vector2::new(axis_angle.cos(), axis_angle.sin());
+    let minor_direction = Vector2::new(-major_direction.y, major_direction.x);
+    let major_length = (conjugate.transpose() * major_direction).norm();
+    let minor_length = (conjugate.transpose() * minor_direction).norm();
+
+    // Parameters are worked out again from where their points end up
+    let parameter = |p: f64| {
+        let pt = conjugate * Vector2::new(p.cos(), p.sin());
+        (pt.dot(&minor_direction) / minor_length)
+            .atan2(pt.dot(&major_direction) / major_length)
+            .rem_euclid(2. * PI)
+    };
+    let (start_parameter, end_parameter) = if is_full_ellipse(ellipse) {
+        (0., 2. * PI)
+    } else if conjugate.determinant() < 0. {
+        // The ellipse goes around the other way once it is moved
+        (
+            parameter(ellipse.end_parameter),
+            parameter(ellipse.start_parameter),
+        )
+    } else {
+        (
+            parameter(ellipse.start_parameter),
+            parameter(ellipse.end_parameter),
+        )
+    };
+
+    Ellipse {
+        center: t.point(&ellipse.center),
+        major_axis: Vector::new(
+            major_direction.x * major_length,
+            major_direction.y * major_length,
+            0.,
+        ),
+        normal: Vector::z_axis(),
+        minor_axis_ratio: minor_length / major_length,
+        start_parameter,
+        end_parameter,
+    }
+}
+
+/// A circle (or arc, going from `start` to `end` in radians) as an ellipse, for transforms that stretch it
+fn circular_ellipse(center: &Point, radius: f64, start: f64, end: f64) -> Ellipse {
+    Ellipse {
+        center: center.clone(),
+        major_axis: Vector::new(radius, 0., 0.),
+        normal: Vector::z_axis(),
+        minor_axis_ratio: 1.,
+        start_parameter: start,
+        end_parameter: end,
+    }
+}
+
+/// The rotation (in degrees) and height of text at `rotation` that is `height` tall, once it is moved
+fn transform_text(t: &Transform, rotation: f64, height: f64) -> (f64, f64) {
+    let up = (rotation + 90.).to_radians();
+    (
+        t.angle(rotation),
+        height * t.direction(up.cos(), up.sin()).norm(),
+    )
+}
+
+/// Moves an entity (in world coordinates) out of its block and into place. Some entities come out as several
+/// entities, like polylines with arcs in them that get stretched into ellipses.
+///
+/// Returns `None` for entities that cannot be moved.
+fn transform_entity(e: Entity, t: &Transform) -> Option<Vec<Entity>> {
+    let specific = match e.specific {
+        EntityType::Line(line) => EntityType::Line(Line {
+            p1: t.point(&line.p1),
+            p2: t.point(&line.p2),
+            ..line
+        }),
+        EntityType::Circle(circle) => match t.uniform_scale() {
+            Some(scale) => EntityType::Circle(Circle {
+                center: t.point(&circle.center),
+                radius: circle.radius * scale,
+                ..circle
+            }),
+            None => EntityType::Ellipse(transform_ellipse(
+                &circular_ellipse(&circle.center, circle.radius, 0., 2. * PI),
+                t,
+            )),
+        },
+        EntityType::Arc(arc) => match t.uniform_scale() {
+            Some(scale) => {
+                // Arcs always go counter-clockwise, so a mirrored arc starts where it used to end
+                let (start_angle, end_angle) = if t.is_mirrored() {
+                    (t.angle(arc.end_angle), t.angle(arc.start_angle))
+                } else {
+                    (t.angle(arc.start_angle), t.angle(arc.end_angle))
+                };
+                EntityType::Arc(Arc {
+                    center: t.point(&arc.center),
+                    radius: arc.radius * scale,
+                    start_angle,
+                    end_angle,
+                    ..arc
+                })
+            }
+            None => EntityType::Ellipse(transform_ellipse(
+                &circular_ellipse(
+                    &arc.center,
+                    arc.radius,
+                    arc.start_angle.to_radians(),
+                    arc.end_angle.to_radians(),
+                ),
+                t,
+            )),
+        },
+        EntityType::Ellipse(ellipse) => EntityType::Ellipse(transform_ellipse(&ellipse, t)),
+        EntityType::LwPolyline(poly) => {
+            let has_bulges = poly.vertices.iter().any(|v| v.bulge != 0.);
+            if has_bulges && t.uniform_scale().is_none() {
+                return transform_entities(explode_lwpolyline(&poly, &e.common), t);
+            }
+            let mut poly = poly;
+            for v in poly.vertices.iter_mut() {
+                let moved = t.point(&Point::new(v.x, v.y, 0.));
+                v.x = moved.x;
+                v.y = moved.y;
+                if t.is_mirrored() {
+                    v.bulge = -v.bulge;
+                }
+            }
+            EntityType::LwPolyline(poly)
+        }
+        EntityType::Polyline(poly) => {
+            let has_bulges = poly.vertices.iter().any(|v| v.bulge != 0.);
+            if is_2d_polyline(&poly) && has_bulges && t.uniform_scale().is_none() {
+                return transform_entities(explode_polyline(&poly, &e.common), t);
+            }
+            let mut poly = poly;
+            for v in poly.vertices.iter_mut() {
+                v.location = t.point(&v.location);
+                if t.is_mirrored() {
+                    v.bulge = -v.bulge;
+                }
+            }
+            EntityType::Polyline(poly)
+        }
+        // Moving the points of a spline moves the whole curve the same way
+        EntityType::Spline(spline) => {
+            let tangent = |pt: &Point| {
+                let moved = t.direction(pt.x, pt.y);
+                Point::new(moved.x, moved.y, pt.z)
+            };
+            EntityType::Spline(Spline {
+                control_points: spline.control_points.iter().map(|pt| t.point(pt)).collect(),
+                fit_points: spline.fit_points.iter().map(|pt| t.point(pt)).collect(),
+                start_tangent: tangent(&spline.start_tangent),
+                end_tangent: tangent(&spline.end_tangent),
+                ..spline
+            })
+        }
+        EntityType::ModelPoint(point) => EntityType::ModelPoint(ModelPoint {
+            location: t.point(&point.location),
+            ..point
+        }),
+        EntityType::Text(text) => {
+            let (rotation, text_height) = transform_text(t, text.rotation, text.text_height);
+            EntityType::Text(Text {
+                location: t.point(&text.location),
+                second_alignment_point: t.point(&text.second_alignment_point),
+                rotation,
+                text_height,
+                ..text
+            })
+        }
+        EntityType::MText(text) => {
+            let (rotation_angle, initial_text_height) =
+                transform_text(t, text.rotation_angle, text.initial_text_height);
+            let x_axis = t.direction(text.x_axis_direction.x, text.x_axis_direction.y);
+            EntityType::MText(MText {
+                insertion_point: t.point(&text.insertion_point),
+                x_axis_direction: Vector::new(x_axis.x, x_axis.y, text.x_axis_direction.z),
+                rotation_angle,
+                initial_text_height,
+                ..text
+            })
+        }
+        _ => return None,
+    };
+
+    Some(vec![Entity {
+        common: e.common,
+        specific,
+    }])
+}
+
+fn transform_entities(entities: Vec<Entity>, t: &Transform) -> Option<Vec<Entity>> {
+    let mut moved = Vec::with_capacity(entities.len());
+    for e in entities {
+        moved.extend(transform_entity(e, t)?);
+    }
+    Some(moved)
+}
+
+/// Entities in a block that are on layer 0, or that are coloured BYBLOCK, take on the layer and colour of the INSERT
+/// that places them
+fn inherit_from_insert(common: &mut EntityCommon, insert: &EntityCommon) {
+    if common.layer == "0" {
+        common.layer = insert.layer.clone();
+    }
+    if common.color.is_by_block() {
+        common.color = insert.color.clone();
+        common.color_24_bit = insert.color_24_bit;
+    }
+}
+
+/// Where an INSERT is being expanded, and what it is inside of
+struct Placement<'a> {
+    /// Moves entities from the block that contains the INSERT into place. This is nothing for INSERTs that are in
+    /// the drawing itself.
+    outer: Transform,
+    /// The INSERT in the drawing that this one is (eventually) placed by. Entities from its block are reported under
+    /// its handle, since they have no handle of their own in the drawing. They are given handles of their own when
+    /// the offset drawing is saved.
+    placed_by: &'a EntityCommon,
+    /// Names of the blocks that this one is inside of
+    parents: Vec<String>,
+}
+
+/// Adds the entities of the block that `insert` places to `expanded`, in world coordinates
+fn expand_insert(
+    insert: &Insert,
+    common: &EntityCommon,
+    placement: &mut Placement,
+    blocks: &[Block],
+    expanded: &mut Vec<Entity>,
+) -> Result<(), KerfAdjustmentError> {
+    let error = |reason| KerfAdjustmentError {
+        reason,
+        entity: Some(Box::new(common.clone())),
+    };
+    if !is_flat(&insert.extrusion_direction) {
+        return Err(error(KerfAdjustmentErrorReason::ThreeDimensionalEntity));
+    }
+    let block = blocks
+        .iter()
+        .find(|block| block.name.eq_ignore_ascii_case(&insert.name))
+        .ok_or_else(|| error(KerfAdjustmentErrorReason::MissingBlock(insert.name.clone())))?;
+    if placement.parents.contains(&block.name) {
+        return Err(error(KerfAdjustmentErrorReason::RecursiveBlock(
+            block.name.clone(),
+        )));
+    }
+
+    let outer = placement.outer;
+    placement.parents.push(block.name.clone());
+    for row in 0..insert.row_count.max(1) {
+        for column in 0..insert.column_count.max(1) {
+            let t = Transform::of_insert(insert, &block.base_point, column, row).then(&outer);
+            for e in block.entities.iter() {
+                let mut e = e.clone();
+                inherit_from_insert(&mut e.common, common);
+                match &e.specific {
+                    EntityType::Insert(inner) => {
+                        placement.outer = t;
+                        expand_insert(inner, &e.common, placement, blocks, expanded)?;
+                    }
+                    // The values of the attributes are kept in the INSERT itself, and the end of the block is not
+                    // part of the drawing
+                    EntityType::AttributeDefinition(_) | EntityType::Seqend(_) => (),
+                    _ => {
+                        e.common.handle = placement.placed_by.handle;
+                        // Entities that cannot be moved (like dimensions and hatches) or cut are copied as they are,
+                        // and are passed through or reported like any other entity that is not offset
+                        let moved = match find_endpoints_of_entity(&e) {
+                            Ok(_) => transform_entity(entity_to_wcs(e.clone()), &t),
+                            Err(_) => None,
+                        };
+                        expanded.extend(moved.unwrap_or_else(|| vec![e]));
+                    }
+                }
+            }
+        }
+    }
+    placement.parents.pop();
+    placement.outer = outer;
+
+    // Attributes are already in place in the coordinates of the block that the INSERT is in
+    for attribute in insert.attributes.iter() {
+        let (rotation, text_height) =
+            transform_text(&outer, attribute.rotation, attribute.text_height);
+        let mut common = common.clone();
+        common.handle = placement.placed_by.handle;
+        expanded.push(Entity {
+            common,
+            specific: EntityType::Attribute(Attribute {
+                location: outer.point(&attribute.location),
+                rotation,
+                text_height,
+                ..attribute.clone()
+            }),
+        });
+    }
+    Ok(())
+}
+
+/// Replaces each INSERT with the entities of the block that it places, moved into place. Blocks that are placed
+/// inside of other blocks are expanded too.
+pub fn expand_inserts(
+    entities: Vec<Entity>,
+    blocks: &[Block],
+) -> Result<Vec<Entity>, KerfAdjustmentError> {
+    let mut expanded = Vec::with_capacity(entities.len());
+    for e in entities {
+        match &e.specific {
+            EntityType::Insert(insert) => {
+                let mut placement = Placement {
+                    outer: Transform::identity(),
+                    placed_by: &e.common,
+                    parents: Vec::new(),
+                };
+                expand_insert(insert, &e.common, &mut placement, blocks, &mut expanded)?;
+            }
+            // Marks the end of an INSERT's attributes, which are gone once the INSERT is expanded. Some writers put
+            // one after every INSERT, even if it has no attributes.
+            EntityType::Seqend(_) => (),
+            _ => expanded.push(e),
+        }
+    }
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod insert_test {
+    use crate::insert::expand_inserts;
+    use dxf::entities::*;
+    use dxf::{Block, Point};
+
+    fn insert(name: &str, location: Point) -> Insert {
+        Insert {
+            name: String::from(name),
+            location,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn test_stretched_and_mirrored_circle_becomes_an_ellipse() {
+        // given: a block with a circle in it, placed stretched along x and mirrored along y
+        let block = Block {
+            name: String::from("hole"),
+            base_point: Point::new(1., 0., 0.),
+            entities: vec![Entity::new(EntityType::Circle(Circle::new(
+                Point::new(1., 0., 0.),
+                1.,
+            )))],
+            ..Default::default()
+        };
+        let stretched = Insert {
+            x_scale_factor: 3.,
+            y_scale_factor: -1.,
+            ..insert("hole", Point::new(10., 10., 0.))
+        };
+
+        // when: we expand the insert
+        let expanded =
+            expand_inserts(vec![Entity::new(EntityType::Insert(stretched))], &[block]).unwrap();
+
+        // then: it is a full ellipse at the insert's location, three times as wide as it is tall
+        assert_eq!(expanded.len(), 1);
+        match &expanded[0].specific {
+            EntityType::Ellipse(ellipse) => {
+                assert!(
+                    (ellipse.center.x - 10.).abs() < 1e-9 && (ellipse.center.y - 10.).abs() < 1e-9
+                );
+                assert!((ellipse.major_axis.x.abs() - 3.).abs() < 1e-9);
+                assert!(ellipse.major_axis.y.abs() < 1e-9);
+                assert!((ellipse.minor_axis_ratio - 1. / 3.).abs() < 1e-9);
+                assert!(
+                    (ellipse.end_parameter - ellipse.start_parameter - 2. * std::f64::consts::PI)
+                        .abs()
+                        < 1e-9
+                );
+            }
+            _ => panic!("stretched circle should be an ellipse"),
+        }
+    }
+
+    #[test]
+    pub fn test_nested_blocks_are_placed_inside_their_parents() {
+        // given: a line in a block, placed rotated by a quarter turn in another block that is placed twice as big
+        let line = Block {
+            name: String::from("line"),
+            entities: vec![Entity::new(EntityType::Line(Line::new(
+                Point::new(0., 0., 0.),
+                Point::new(1., 0., 0.),
+            )))],
+            ..Default::default()
+        };
+        let inner = Insert {
+            rotation: 90.,
+            ..insert("line", Point::new(1., 0., 0.))
+        };
+        let outer_block = Block {
+            name: String::from("outer"),
+            entities: vec![Entity::new(EntityType::Insert(inner))],
+            ..Default::default()
+        };
+        let outer = Insert {
+            x_scale_factor: 2.,
+            y_scale_factor: 2.,
+            ..insert("outer", Point::new(5., 5., 0.))
+        };
+
+        // when: we expand the outer insert
+        let expanded = expand_inserts(
+            vec![Entity::new(EntityType::Insert(outer))],
+            &[line, outer_block],
+        )
+        .unwrap();
+
+        // then: the line is turned a quarter turn, and then moved and scaled by the outer insert
+        match &expanded[..] {
+            [Entity {
+                specific: EntityType::Line(line),
+                ..
+            }] => {
+                assert!((line.p1.x - 7.).abs() < 1e-9 && (line.p1.y - 5.).abs() < 1e-9);
+                assert!((line.p2.x - 7.).abs() < 1e-9 && (line.p2.y - 7.).abs() < 1e-9);
+            }
+            _ => panic!("there should be exactly one line"),
+        }
+    }
+
+    #[test]
+    pub fn test_blocks_that_place_themselves_are_an_error() {
+        // given: a block that places itself, and an insert of a block that does not exist
+        let recursive = Block {
+            name: String::from("forever"),
+            entities: vec![Entity::new(EntityType::Insert(insert(
+                "forever",
+                Point::origin(),
+            )))],
+            ..Default::default()
+        };
+
+        // when: we expand inserts of each of them
+        let forever = expand_inserts(
+            vec![Entity::new(EntityType::Insert(insert(
+                "forever",
+                Point::origin(),
+            )))],
+            &[recursive],
+        );
+        let missing = expand_inserts(
+            vec![Entity::new(EntityType::Insert(insert(
+                "nowhere",
+                Point::origin(),
+            )))],
+            &[],
+        );
+
+        // then: neither can be expanded
+        assert_eq!(
+            forever.err().map(|e| e.kind()).as_deref(),
+            Some("RecursiveBlock")
+        );
+        assert_eq!(
+            missing.err().map(|e| e.kind()).as_deref(),
+            Some("MissingBlock")
+        );
+    }
+}
@@ -0,0 +1,346 @@
+//! How far each contour is offset
+//!
+//! Drawings often keep different materials or operations on different layers, and each of them has its own kerf.
+//! Laser software commonly picks the operation by the colour of the entity instead, so kerfs can be given for
+//! colours too. Colours can also be marked as not being offset at all, for things like engraving or scoring.
+//!
+//! The kerf can be given in a unit of length, and is converted into the units that the drawing is in.
+use crate::errors::{KerfAdjustmentError, KerfAdjustmentErrorReason};
+use crate::options::UnspecifiedUnits;
+use crate::report::OffsetReport;
+use dxf::entities::EntityCommon;
+use dxf::enums::{DrawingUnits, Units};
+use dxf::tables::Layer;
+use dxf::{Color, Header};
+use std::collections::HashMap;
+use wasm_bindgen::prelude::*;
+
+/// The unit that a kerf is given in
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum KerfUnit {
+    /// Whatever units the drawing is in, so the kerf is not converted
+    #[default]
+    DrawingUnits,
+    Millimeters,
+    Centimeters,
+    Meters,
+    Inches,
+    Feet,
+}
+
+impl KerfUnit {
+    fn in_millimeters(self) -> Option<f64> {
+        match self {
+            KerfUnit::DrawingUnits => None,
+            KerfUnit::Millimeters => Some(1.),
+            KerfUnit::Centimeters => Some(10.),
+            KerfUnit::Meters => Some(1000.),
+            KerfUnit::Inches => Some(25.4),
+            KerfUnit::Feet => Some(304.8),
+        }
+    }
+}
+
+/// How long a drawing unit is in millimetres, or `None` for unitless drawings
+fn units_in_millimeters(units: Units) -> Option<f64> {
+    let millimeters = match units {
+        Units::Unitless => return None,
+        Units::Inches => 25.4,
+        Units::Feet => 304.8,
+        Units::Miles => 1_609_344.,
+        Units::Millimeters => 1.,
+        Units::Centimeters => 10.,
+        Units::Meters => 1e3,
+        Units::Kilometers => 1e6,
+        Units::Microinches => 25.4e-6,
+        Units::Mils => 25.4e-3,
+        Units::Yards => 914.4,
+        Units::Angstroms => 1e-7,
+        Units::Nanometers => 1e-6,
+        Units::Microns => 1e-3,
+        Units::Decimeters => 100.,
+        Units::Decameters => 1e4,
+        Units::Hectometers => 1e5,
+        Units::Gigameters => 1e12,
+        Units::AstronomicalUnits => 1.495_978_707e14,
+        Units::LightYears => 9.460_730_472_580_8e18,
+        Units::Parsecs => 3.085_677_581_491_367e19,
+        Units::USSurveyFeet => 1200. / 3937. * 1e3,
+        Units::USSurveyInch => 100. / 3937. * 1e3,
+        Units::USSurveyYard => 3600. / 3937. * 1e3,
+        Units::USSurveyMile => 6_336_000. / 3937. * 1e3,
+    };
+    Some(millimeters)
+}
+
+/// The colour that an entity is drawn in, once BYLAYER has been looked up in the layer table
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum ResolvedColor {
+    /// An AutoCAD Color Index (ACI)
+    Index(u8),
+    /// A true colour, as 0xRRGGBB
+    TrueColor(u32),
+}
+
+/// How far contours are offset, depending on their colour and the layer that they are on
+///
+/// Kerfs for colours come first, then kerfs for layers, and then the default.
+#[wasm_bindgen]
+#[derive(Clone, Debug, Default)]
+pub struct KerfMap {
+    /// How far contours on layers that are not in the map are offset
+    pub default: f64,
+    /// The unit that all of the kerfs in the map are given in
+    pub unit: KerfUnit,
+    layers: HashMap<String, f64>,
+    /// `None` for colours that are not offset
+    colors: HashMap<ResolvedColor, Option<f64>>,
+}
+
+#[wasm_bindgen]
+impl KerfMap {
+    #[wasm_bindgen(constructor)]
+    pub fn new(default: f64) -> Self {
+        Self {
+            default,
+            ..Default::default()
+        }
+    }
+
+    /// Sets how far contours on the layer are offset, instead of the default. Layer names are not case sensitive.
+    pub fn set_layer(&mut self, layer: &str, amount: f64) {
+        self.layers.insert(layer.to_uppercase(), amount);
+    }
+
+    /// How far contours on the layer are offset
+    pub fn for_layer(&self, layer: &str) -> f64 {
+        self.layers
+            .get(&layer.to_uppercase())
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    /// Sets how far contours drawn in the AutoCAD Color Index colour are offset, whatever layer they are on
+    pub fn set_color(&mut self, index: u8, amount: f64) {
+        self.colors.insert(ResolvedColor::Index(index), Some(amount));
+    }
+
+    /// Sets how far contours drawn in the true colour (0xRRGGBB) are offset, whatever layer they are on
+    pub fn set_true_color(&mut self, rgb: u32, amount: f64) {
+        self.colors.insert(ResolvedColor::TrueColor(rgb), Some(amount));
+    }
+
+    /// Leaves entities drawn in the AutoCAD Color Index colour as they are, like entities that are not cut
+    pub fn skip_color(&mut self, index: u8) {
+        self.colors.insert(ResolvedColor::Index(index), None);
+    }
+
+    /// Leaves entities drawn in the true colour (0xRRGGBB) as they are, like entities that are not cut
+    pub fn skip_true_color(&mut self, rgb: u32) {
+        self.colors.insert(ResolvedColor::TrueColor(rgb), None);
+    }
+}
+
+impl KerfMap {
+    /// The same kerfs, converted into the units that the drawing is in.
+    ///
+    /// Drawings that do not set $INSUNITS are taken to be in millimetres or inches, going by $MEASUREMENT, unless
+    /// `unspecified` says not to.
+    pub fn in_drawing_units(
+        &self,
+        header: &Header,
+        unspecified: UnspecifiedUnits,
+        report: &mut OffsetReport,
+    ) -> Result<Self, KerfAdjustmentError> {
+        let kerf_unit = match self.unit.in_millimeters() {
+            Some(kerf_unit) => kerf_unit,
+            None => return Ok(self.clone()),
+        };
+        let drawing_unit = match units_in_millimeters(header.default_drawing_units) {
+            Some(drawing_unit) => drawing_unit,
+            None if unspecified == UnspecifiedUnits::Refuse => {
+                return Err(KerfAdjustmentErrorReason::UnspecifiedDrawingUnits.into())
+            }
+            None => {
+                let assumed = match header.drawing_units {
+                    DrawingUnits::Metric => KerfUnit::Millimeters,
+                    DrawingUnits::English => KerfUnit::Inches,
+                };
+                report.assumed_units = Some(assumed);
+                assumed.in_millimeters().unwrap_or(1.)
+            }
+        };
+
+        let scale = kerf_unit / drawing_unit;
+        Ok(Self {
+            default: self.default * scale,
+            unit: KerfUnit::DrawingUnits,
+            layers: self
+                .layers
+                .iter()
+                .map(|(layer, amount)| (layer.clone(), amount * scale))
+                .collect(),
+            colors: self
+                .colors
+                .iter()
+                .map(|(color, amount)| (*color, amount.map(|amount| amount * scale)))
+                .collect(),
+        })
+    }
+
+    /// How far contours that start with the entity are offset, or `None` if the entity is not offset at all.
+    /// `layers` is the layer table of the drawing, which BYLAYER colours are looked up in.
+    pub fn for_entity(&self, common: &EntityCommon, layers: &[Layer]) -> Option<f64> {
+        let color_setting = resolve_colors(common, layers)
+            .into_iter()
+            .find_map(|color| self.colors.get(&color));
+        match color_setting {
+            Some(setting) => *setting,
+            None => Some(self.for_layer(&common.layer)),
+        }
+    }
+}
+
+/// Whether two layer names name the same layer. Like AutoCAD, layer names are not case sensitive.
+pub(crate) fn is_same_layer(a: &str, b: &str) -> bool {
+    a.to_uppercase() == b.to_uppercase()
+}
+
+/// The AutoCAD Color Index colour that BYBLOCK entities are drawn in when they are not in a block (white)
+const BY_BLOCK_OUTSIDE_BLOCK: u8 = 7;
+
+/// The ACI colour of a layer. Layers that are turned off have the negative of their colour, which the dxf crate has
+/// no getter for, so it is read out of the `Debug` form of the colour.
+fn layer_color_index(color: &Color) -> Option<u8> {
+    if !color.is_turned_off() {
+        return color.index();
+    }
+    let digits = format!("{:?}", color)
+        .chars()
+        .filter(char::is_ascii_digit)
+        .collect::<String>();
+    digits.parse::<u8>().ok().filter(|index| *index != 0)
+}
+
+/// The colours that the entity is drawn in, most specific first. Entities with a true colour also have the closest
+/// ACI colour, for programs that do not understand true colours.
+///
+/// Entities from blocks have already taken on the colour of the INSERT that placed them if they are BYBLOCK, so any
+/// BYBLOCK entity left is drawn the way AutoCAD draws one that is not in a block.
+pub(crate) fn resolve_colors(common: &EntityCommon, layers: &[Layer]) -> Vec<ResolvedColor> {
+    let mut colors = Vec::with_capacity(2);
+    if common.color_24_bit != 0 {
+        colors.push(ResolvedColor::TrueColor(common.color_24_bit as u32 & 0xFF_FFFF));
+    }
+
+    let index = if common.color.is_by_layer() {
+        layers
+            .iter()
+            .find(|layer| is_same_layer(&layer.name, &common.layer))
+            .and_then(|layer| layer_color_index(&layer.color))
+    } else if common.color.is_by_block() {
+        Some(BY_BLOCK_OUTSIDE_BLOCK)
+    } else {
+        common.color.index()
+    };
+    colors.extend(index.map(ResolvedColor::Index));
+    colors
+}
+
+#[cfg(test)]
+mod kerf_test {
+    use crate::kerf::KerfMap;
+    use dxf::entities::EntityCommon;
+    use dxf::tables::Layer;
+    use dxf::Color;
+
+    #[test]
+    pub fn test_kerf_is_picked_by_color_then_layer() {
+        // given: a kerf for red, a kerf for a green true colour, a kerf for a layer, and blue left alone
+        let mut kerfs = KerfMap::new(0.5);
+        kerfs.set_color(1, 0.1);
+        kerfs.set_true_color(0x00FF00, 0.2);
+        kerfs.set_layer("wood", 0.3);
+        kerfs.skip_color(5);
+        let layers = vec![Layer {
+            name: String::from("engrave"),
+            color: Color::from_index(5),
+            ..Default::default()
+        }];
+        let entity = |layer: &str, color: Color, color_24_bit: i32| EntityCommon {
+            layer: String::from(layer),
+            color,
+            color_24_bit,
+            ..Default::default()
+        };
+
+        // when: we look up the kerf for entities in those colours and on those layers
+        let red = kerfs.for_entity(&entity("wood", Color::from_index(1), 0), &layers);
+        let green = kerfs.for_entity(&entity("wood", Color::from_index(3), 0x00FF00), &layers);
+        let wood = kerfs.for_entity(&entity("wood", Color::by_layer(), 0), &layers);
+        let engrave = kerfs.for_entity(&entity("engrave", Color::by_layer(), 0), &layers);
+        let other = kerfs.for_entity(&entity("0", Color::from_index(2), 0), &layers);
+
+        // then: colours win over layers, BYLAYER uses the colour of the layer, and blue is not offset
+        assert_eq!(red, Some(0.1));
+        assert_eq!(green, Some(0.2));
+        assert_eq!(wood, Some(0.3));
+        assert_eq!(engrave, None);
+        assert_eq!(other, Some(0.5));
+    }
+
+    #[test]
+    pub fn test_kerf_is_picked_for_byblock_colours_and_layers_that_are_off() {
+        // given: a kerf for red and a kerf for white, and a layer that is turned off, whose colour is red
+        let mut kerfs = KerfMap::new(0.5);
+        kerfs.set_color(1, 0.1);
+        kerfs.set_color(7, 0.2);
+        let mut off = Color::from_index(1);
+        off.turn_off();
+        let layers = vec![Layer {
+            name: String::from("hidden"),
+            color: off,
+            ..Default::default()
+        }];
+        let entity = |layer: &str, color: Color| EntityCommon {
+            layer: String::from(layer),
+            color,
+            ..Default::default()
+        };
+
+        // when: we look up the kerf for a BYLAYER entity on that layer, and a BYBLOCK entity that is not in a block
+        let hidden = kerfs.for_entity(&entity("hidden", Color::by_layer()), &layers);
+        let by_block = kerfs.for_entity(&entity("0", Color::by_block()), &layers);
+
+        // then: the layer is still red, and the BYBLOCK entity is white, the way AutoCAD draws them
+        assert_eq!(hidden, Some(0.1));
+        assert_eq!(by_block, Some(0.2));
+    }
+
+    #[test]
+    pub fn test_layer_names_are_not_case_sensitive() {
+        // given: a kerf for the wood layer, and an engraving layer in the layer table, named in mixed case
+        let mut kerfs = KerfMap::new(0.5);
+        kerfs.set_layer("Wood", 0.3);
+        kerfs.skip_color(5);
+        let layers = vec![Layer {
+            name: String::from("Engrave"),
+            color: Color::from_index(5),
+            ..Default::default()
+        }];
+        let entity = |layer: &str| EntityCommon {
+            layer: String::from(layer),
+            ..Default::default()
+        };
+
+        // when: we look up the kerf for entities whose layers are named in other cases
+        let wood = kerfs.for_entity(&entity("WOOD"), &layers);
+        let engrave = kerfs.for_entity(&entity("engrave"), &layers);
+
+        // then: they are found all the same
+        assert_eq!(wood, Some(0.3));
+        assert_eq!(kerfs.for_layer("wood"), 0.3);
+        assert_eq!(engrave, None);
+    }
+}
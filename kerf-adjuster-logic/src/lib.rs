@@ -1,100 +1,531 @@
 use wasm_bindgen::prelude::*;
 
-use console_error_panic_hook;
-use dxf::Drawing;
+use dxf::{Block, Drawing};
+use std::collections::HashSet;
 use std::io::BufReader;
+mod cleanup;
 pub mod contour;
-use contour::{Contour, ContourVecToDxf};
+mod curve;
+mod ellipse;
+mod endpoint_index;
+use contour::{Contour, ContourVecToDxf, EntityEnds};
 pub mod errors;
-
-/// Merges the `head` contour with one of the `tail` contours if possible
-/// 
-/// If the head contour X can be combined with one of the tail contours Y
-///     Then it returns a vector containing the combination of X and Y, as well as the remaining uncombined tail contours
-/// 
-/// If the head contour X cannot be combined with any tail contour
-///     Then it returns None
-fn collapse_contours_once(head: &Contour, tail: &[Contour]) -> Option<Vec<Contour>> {
-    for (i, contour) in tail.iter().enumerate() {
-        if let Ok(combined) = head
-            .clone()
-            .combine_attempt(contour.clone())
-        {
-            let mut ret = tail.to_vec();
-            ret.remove(i);
-            ret.push(combined);
-
-            return Some(ret);
-        }
-    }
-    return None;
-}
-
+mod geometry;
+mod graph;
+mod heal;
+mod insert;
+pub mod kerf;
+pub mod nesting;
+mod ocs;
+mod offset;
+pub mod options;
+mod polyline;
+pub mod report;
+pub mod segment;
+mod spline;
+use dxf::entities::{Entity, EntityType};
+use dxf::tables::Layer;
+use endpoint_index::EndpointIndex;
+use errors::{KerfAdjustmentError, KerfAdjustmentErrorReason};
+use kerf::{is_same_layer, KerfMap, KerfUnit, ResolvedColor};
+use options::{FailedContour, OffsetOptions, OpenContour};
+use segment::Segment;
+use report::{ContourError, EntityError, OffsetReport, OffsetResult, PassedThroughEntity};
 
 /// Maximizes the number of closed contours in a contour list by combining them
 /// 
 /// e.g If we have a vector of 4 open contours, where each one is the side of a rectangle, 
 /// it will return a vector of 1 closed contour where the  
-fn collapse_contours(mut contours: Vec<Contour>) -> Vec<Contour> {
+///
+/// Contours are worked through like a queue. The contour at the front is combined with the first contour in the
+/// queue that it can be combined with, and the result goes to the back of the queue. If it cannot be combined with
+/// anything, it is complete. The end points are kept in a spatial index, so that finding the contours that can be
+/// combined with does not mean going through the whole queue.
+///
+/// Contours whose ends are within `tolerance` of each other can always be combined, so `CannotJoinContours` would
+/// mean that the index found ends that are not close enough.
+///
+/// This is only public so that the benchmarks and tests can compare it against other ways of joining contours.
+#[doc(hidden)]
+pub fn collapse_contours(
+    contours: Vec<Contour>,
+    tolerance: f64,
+) -> Result<Vec<Contour>, KerfAdjustmentError> {
     let mut final_contours = Vec::new();
 
-    loop {
-        // Go through the contours
-        match contours.as_slice() {
-            // Stop if there are no contours left to combine
-            [] => break, 
-
-            // Separate the first contour from the remaining ones
-            [head, tail @ ..] => {
-                match collapse_contours_once(head, tail) {
-                    // If we couldn't combine it, `head` must be a complete contour
-                    None => {
-                        final_contours.push(head.clone());
-                        contours = tail.to_vec(); // remove head from contours list
-                    },
-                    Some(new_contours) => contours = new_contours
+    // Contours that have been combined into another contour are taken out of their slot. Slots are only ever added to
+    // the end, so the order of the slots is the order of the queue.
+    let mut slots = contours.into_iter().map(Some).collect::<Vec<_>>();
+    let mut index = EndpointIndex::new(tolerance);
+    let add_to_index = |index: &mut EndpointIndex, slot: usize, c: &Contour| {
+        if let Some((a, b)) = c.end_points {
+            index.insert(slot, a);
+            index.insert(slot, b);
+        }
+    };
+    for (slot, c) in slots.iter().enumerate() {
+        if let Some(c) = c {
+            add_to_index(&mut index, slot, c);
+        }
+    }
+
+    let mut front = 0;
+    while front < slots.len() {
+        let head = match slots[front].take() {
+            Some(head) => head,
+            None => {
+                front += 1;
+                continue;
+            }
+        };
+
+        // The contour closest to the front of the queue with an end close enough to one of ours
+        let partner = head.end_points.and_then(|(a, b)| {
+            index
+                .near(&a, tolerance)
+                .chain(index.near(&b, tolerance))
+                .filter(|(slot, pt)| {
+                    let is_close = (pt - a).magnitude() < tolerance || (pt - b).magnitude() < tolerance;
+                    slots[*slot].is_some() && is_close
+                })
+                .map(|(slot, _)| slot)
+                .min()
+        });
+
+        match partner.and_then(|slot| slots[slot].take()) {
+            // If we couldn't combine it, `head` must be a complete contour
+            None => final_contours.push(head),
+            Some(other) => match head.combine_attempt(other, tolerance) {
+                Ok(combined) => {
+                    add_to_index(&mut index, slots.len(), &combined);
+                    slots.push(Some(combined));
                 }
+                Err(contours) => {
+                    let (head, _) = *contours;
+                    return Err(KerfAdjustmentError {
+                        reason: KerfAdjustmentErrorReason::CannotJoinContours,
+                        entity: head
+                            .segments
+                            .first()
+                            .map(|segment| Box::new(segment.source.common.clone())),
+                    });
+                }
+            },
+        }
+        front += 1;
+    }
+
+    Ok(final_contours)
+}
+
+/// Replaces a spline or ellipse with a polyline that follows it closely enough, so that it can be offset like any
+/// other shape. Other entities are returned as they are.
+fn approximate_curves(e: Entity, options: &OffsetOptions, report: &mut OffsetReport) -> Entity {
+    let approximation = match &e.specific {
+        EntityType::Spline(spline) => spline::approximate_spline(spline, options.curve_tolerance),
+        EntityType::Ellipse(ellipse) => {
+            ellipse::approximate_ellipse(ellipse, options.curve_tolerance)
+        }
+        _ => None,
+    };
+
+    match approximation {
+        Some(approximation) => {
+            report.max_curve_deviation = report
+                .max_curve_deviation
+                .max(approximation.max_deviation);
+            let poly = curve::approximation_to_lwpolyline(&approximation);
+            Entity {
+                common: e.common,
+                specific: EntityType::LwPolyline(poly),
             }
         }
+        None => e,
     }
+}
 
-    return final_contours;
+/// Gives back `error` if `options` say to refuse drawings with entities that cannot be offset, and otherwise lists
+/// the entity in the report so that it can be passed through as it is
+fn refuse_or_report(
+    e: &Entity,
+    error: KerfAdjustmentError,
+    options: &OffsetOptions,
+    report: &mut OffsetReport,
+) -> Result<(), KerfAdjustmentError> {
+    match options.failed_contour {
+        FailedContour::Refuse => Err(error),
+        FailedContour::Report => {
+            report.entity_errors.push(EntityError::new(e, &error));
+            Ok(())
+        }
+    }
 }
 
-fn drawing_to_contours(drawing: Drawing) -> Vec<Contour> {
-    // Convert each DXF entity (arc, circle, text, etc) into a "Contour" which can be more easily manipulated by us
-    let contours = drawing
-        .entities
-        .clone()
-        .into_iter()
-        .map(Contour::create_from_entity)
-        .collect::<Vec<_>>();
+/// Gives back `error` if `options` say to refuse drawings with contours that cannot be offset, and otherwise lists the
+/// contour in the report and adds the entities it was made from to `unchanged`, so that they can be written back out
+/// as they were
+fn refuse_or_report_contour(
+    entities: Vec<Entity>,
+    error: KerfAdjustmentError,
+    options: &OffsetOptions,
+    report: &mut OffsetReport,
+    unchanged: &mut Vec<Entity>,
+) -> Result<(), KerfAdjustmentError> {
+    match options.failed_contour {
+        FailedContour::Refuse => Err(error),
+        FailedContour::Report => {
+            report.contour_errors.push(ContourError::new(&entities, &error));
+            unchanged.extend(entities);
+            Ok(())
+        }
+    }
+}
+
+/// The entities of a drawing, sorted by what is done with them
+struct SortedEntities {
+    /// Contours made of the entities that are cut, one for each entity
+    contours: Vec<Contour>,
+    /// Entities that are cut, but have nothing in them to offset (like a closed polyline whose vertices are all in
+    /// the same place). They would make contours with no segments, which have no entities to write back out.
+    empty: Vec<Entity>,
+    /// Entities that are not cut, or that could not be made into contours, in the order they were in
+    passed_through: Vec<Entity>,
+}
+
+/// Converts each DXF entity that gets cut (arc, circle, line, etc) into a "Contour" which can be more easily
+/// manipulated by us. Entities that are not cut (like text), that are in a colour that `kerfs` says is not offset, or
+/// that cannot be made into contours (if `options` do not refuse them), are given back separately, as they are and in
+/// the order they were in.
+fn drawing_to_contours(
+    entities: Vec<Entity>,
+    kerfs: &KerfMap,
+    layers: &[Layer],
+    blocks: &[Block],
+    options: &OffsetOptions,
+    report: &mut OffsetReport,
+) -> Result<SortedEntities, KerfAdjustmentError> {
+    let mut contours = Vec::new();
+    let mut empty = Vec::new();
+    let mut passed_through = Vec::new();
+    for e in entities {
+        // repeated parts are often placed as blocks, whose entities need to be offset where they are placed
+        let placed = match &e.specific {
+            EntityType::Insert(_) | EntityType::Seqend(_) => {
+                match insert::expand_inserts(vec![e.clone()], blocks) {
+                    Ok(placed) => placed,
+                    Err(error) => {
+                        refuse_or_report(&e, error, options, report)?;
+                        passed_through.push(e);
+                        continue;
+                    }
+                }
+            }
+            _ => vec![e],
+        };
+
+        for e in placed {
+            let is_offset = kerfs.for_entity(&e.common, layers).is_some();
+            let ends = if is_offset {
+                match contour::find_endpoints_of_entity(&e) {
+                    Ok(ends) => ends,
+                    Err(error) => {
+                        refuse_or_report(&e, error, options, report)?;
+                        passed_through.push(e);
+                        continue;
+                    }
+                }
+            } else {
+                EntityEnds::PassThrough
+            };
+            match ends {
+                EntityEnds::PassThrough => {
+                    report.passed_through.push(PassedThroughEntity::new(&e));
+                    passed_through.push(e);
+                }
+                EntityEnds::Open(..) | EntityEnds::Closed => {
+                    let e = approximate_curves(e, options, report);
+                    if Segment::of_entity(&e).is_some_and(|shapes| !shapes.is_empty()) {
+                        contours.push(Contour::create_from_entity(e));
+                    } else {
+                        empty.push(e);
+                    }
+                }
+            }
+        }
+    }
+    Ok(SortedEntities {
+        contours,
+        empty,
+        passed_through,
+    })
+}
 
+/// Joins up open contours whose ends are within `tolerance` of each other
+fn join_open_contours(
+    contours: Vec<Contour>,
+    tolerance: f64,
+    report: &mut OffsetReport,
+) -> Result<Vec<Contour>, KerfAdjustmentError> {
     // Partition the contours by whether or not they are open (i.e can be joined to another contour)
     let (contours, mut finished_contours) = contours
         .into_iter()
         .partition::<Vec<_>, _>(|c| c.is_open());
 
-    finished_contours.extend(collapse_contours(contours));
+    // Where more than two contours meet, joining them end to end could go more than one way
+    let extracted = graph::extract_faces(contours, tolerance);
+    report.junctions.extend(extracted.junctions);
+    finished_contours.extend(extracted.faces);
+
+    finished_contours.extend(collapse_contours(extracted.remaining, tolerance)?);
 
-    return finished_contours;
+    Ok(finished_contours)
+}
+
+/// Splits the contours up by the layer they are on, the colour they are drawn in and the amount they are offset by,
+/// keeping the groups in the order they first show up in
+fn group_by_layer_color_and_kerf(
+    contours: Vec<Contour>,
+    kerf_of: impl Fn(&Contour) -> f64,
+    colors_of: impl Fn(&Contour) -> Vec<ResolvedColor>,
+) -> Vec<Vec<Contour>> {
+    let mut groups: Vec<Vec<Contour>> = Vec::new();
+    for c in contours {
+        let is_same_group = |group: &Vec<Contour>| {
+            is_same_layer(group[0].layer(), c.layer())
+                && colors_of(&group[0]) == colors_of(&c)
+                && kerf_of(&group[0]) == kerf_of(&c)
+        };
+        match groups.iter_mut().find(|group| is_same_group(group)) {
+            Some(group) => group.push(c),
+            None => groups.push(vec![c]),
+        }
+    }
+    groups
+}
+
+/// Entities made by offsetting another keep its handle, and entities from blocks have the handle of the INSERT that
+/// placed them. Only the first entity with each handle from the drawing gets to keep it, and entities from blocks never
+/// do, unless the INSERT itself could not be expanded. The others are given new handles when the drawing is saved.
+fn clear_reused_handles(entities: &mut [Entity], insert_handles: &HashSet<u32>) {
+    let mut used_handles = HashSet::new();
+    for e in entities {
+        let handle = e.common.handle;
+        let is_from_block = insert_handles.contains(&handle) && !matches!(e.specific, EntityType::Insert(_));
+        if is_from_block || (handle != 0 && !used_handles.insert(handle)) {
+            e.common.handle = 0;
+        }
+    }
+}
+
+/// Heals the gaps between the contours on one layer, and joins them up
+fn join_layer(
+    contours: Vec<Contour>,
+    options: &OffsetOptions,
+    report: &mut OffsetReport,
+) -> Result<Vec<Contour>, KerfAdjustmentError> {
+    // close up any gaps that are too big to join, but small enough to heal
+    let (contours, healed_gaps) = heal::heal_gaps(
+        contours,
+        options.heal_distance,
+        options.join_tolerance,
+        options.gap_repair,
+    );
+    report.healed_gaps.extend(healed_gaps);
+    join_open_contours(contours, options.join_tolerance, report)
+}
+
+/// Called when the WebAssembly module is loaded. In development builds, panics are written to the browser console
+/// along with where they happened.
+#[wasm_bindgen(start)]
+pub fn start() {
+    #[cfg(debug_assertions)]
+    console_error_panic_hook::set_once();
+}
+
+/// Offsets the drawing. In JavaScript, a `KerfAdjustmentError` is thrown if it cannot be offset, including if any
+/// contour or entity in it cannot be offset.
+#[wasm_bindgen]
+pub fn offset_drawing(
+    drawing_bytes: &[u8],
+    offset_amount: f64,
+) -> Result<Vec<u8>, KerfAdjustmentError> {
+    offset_drawing_with_options(drawing_bytes, offset_amount, &OffsetOptions::default())
+}
+
+/// Offsets the drawing. There is no report to list contours or entities that could not be offset in, so the drawing
+/// is refused if there are any, whatever `options.failed_contour` says. Use `offset_drawing_with_report` to get back
+/// a drawing with them left as they were.
+#[wasm_bindgen]
+pub fn offset_drawing_with_options(
+    drawing_bytes: &[u8],
+    offset_amount: f64,
+    options: &OffsetOptions,
+) -> Result<Vec<u8>, KerfAdjustmentError> {
+    let options = OffsetOptions {
+        failed_contour: FailedContour::Refuse,
+        ..options.clone()
+    };
+    Ok(offset_drawing_with_report(drawing_bytes, offset_amount, &options)?.drawing())
 }
 
+/// Offsets the drawing, and also reports on what had to be done to it along the way
 #[wasm_bindgen]
-pub fn offset_drawing(drawing_bytes: &[u8], offset_amount: f64) -> Vec<u8> {
+pub fn offset_drawing_with_report(
+    drawing_bytes: &[u8],
+    offset_amount: f64,
+    options: &OffsetOptions,
+) -> Result<OffsetResult, KerfAdjustmentError> {
+    offset_drawing_with_kerf_map(drawing_bytes, &KerfMap::new(offset_amount), options)
+}
+
+/// Offsets the drawing by a kerf that is given in `unit`, converting it into the units that the drawing is in
+#[wasm_bindgen]
+pub fn offset_drawing_with_unit(
+    drawing_bytes: &[u8],
+    offset_amount: f64,
+    unit: KerfUnit,
+    options: &OffsetOptions,
+) -> Result<OffsetResult, KerfAdjustmentError> {
+    let mut kerfs = KerfMap::new(offset_amount);
+    kerfs.unit = unit;
+    offset_drawing_with_kerf_map(drawing_bytes, &kerfs, options)
+}
+
+/// Offsets the drawing, offsetting the contours on each layer by the amount in `kerfs` for that layer. Contours are
+/// never joined up with contours on other layers.
+#[wasm_bindgen]
+pub fn offset_drawing_with_kerf_map(
+    drawing_bytes: &[u8],
+    kerfs: &KerfMap,
+    options: &OffsetOptions,
+) -> Result<OffsetResult, KerfAdjustmentError> {
+    let mut report = OffsetReport::default();
     let mut bufreader = BufReader::new(drawing_bytes);
-    let drawing = Drawing::load(&mut bufreader).unwrap();
-    let drawing_contours = drawing_to_contours(drawing);
+    let mut drawing = Drawing::load(&mut bufreader)
+        .map_err(|e| KerfAdjustmentError::from(KerfAdjustmentErrorReason::InvalidDxf(e.to_string())))?;
+    let kerfs = &kerfs.in_drawing_units(&drawing.header, options.unspecified_units, &mut report)?;
+    // entities from blocks have the handle of the INSERT that placed them until the drawing is written out, so that
+    // it is what gets reported
+    let insert_handles = drawing
+        .entities
+        .iter()
+        .filter(|e| matches!(e.specific, EntityType::Insert(_)))
+        .map(|e| e.common.handle)
+        .collect::<HashSet<_>>();
+    let SortedEntities {
+        contours,
+        empty,
+        passed_through,
+    } = drawing_to_contours(
+        std::mem::take(&mut drawing.entities),
+        kerfs,
+        &drawing.layers,
+        &drawing.blocks,
+        options,
+        &mut report,
+    )?;
+    let kerf_of = |c: &Contour| {
+        c.segments
+            .first()
+            .and_then(|segment| kerfs.for_entity(&segment.source.common, &drawing.layers))
+            .unwrap_or(kerfs.default)
+    };
 
-    // offset the contours
-    let new_drawing = drawing_contours
-        .into_iter()
-        .map(|c| c.offset_contour(offset_amount).unwrap_or(c))
-        .collect::<Vec<_>>()
-        .to_dxf();
+    // the join tolerance can depend on how big the drawing is
+    let options = &options.for_extents(contour::extents_size(&contours));
+
+    let colors_of = |c: &Contour| {
+        c.segments
+            .first()
+            .map(|segment| kerf::resolve_colors(&segment.source.common, &drawing.layers))
+            .unwrap_or_default()
+    };
+
+    // contours on different layers, in different colours (which laser software often cuts as separate operations) or
+    // that are offset by different amounts are never joined up with each other
+    let mut drawing_contours = Vec::new();
+    for layer_contours in group_by_layer_color_and_kerf(contours, kerf_of, colors_of) {
+        drawing_contours.extend(join_layer(layer_contours, options, &mut report)?);
+    }
+    report
+        .junctions
+        .sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+
+    // contours whose ends do not meet are left as they are, refused, or outlined, rather than offset to one side
+    report.open_paths = drawing_contours.iter().filter_map(Contour::open_path).collect();
+    if options.open_contour == OpenContour::Refuse && !report.open_paths.is_empty() {
+        let entity = drawing_contours
+            .iter()
+            .find(|c| c.is_open())
+            .and_then(|c| c.segments.first())
+            .map(|segment| Box::new(segment.source.common.clone()));
+        return Err(KerfAdjustmentError {
+            reason: KerfAdjustmentErrorReason::OpenContours(report.open_paths),
+            entity,
+        });
+    }
+
+    // work out which contours are parts and which are holes in parts, which can be on different layers
+    let depths = nesting::nesting_depths(&drawing_contours);
+
+    // offset the contours, growing the parts and shrinking the holes by the kerf for their colour or layer
+    let mut offset_contours = Vec::with_capacity(drawing_contours.len());
+    let mut markers = Vec::new();
+    // entities of contours that could not be offset, which are written back out as they were
+    let mut unchanged = Vec::new();
+    for e in empty {
+        let error = KerfAdjustmentError {
+            reason: KerfAdjustmentErrorReason::CannotOffsetEmptyContour,
+            entity: Some(Box::new(e.common.clone())),
+        };
+        refuse_or_report_contour(vec![e], error, options, &mut report, &mut unchanged)?;
+    }
+    for (c, depth) in drawing_contours.into_iter().zip(depths) {
+        let offset_amount = kerf_of(&c);
+        let amount = if nesting::is_hole(depth) {
+            -offset_amount
+        } else {
+            offset_amount
+        };
+        let offset = match (c.is_open(), options.open_contour) {
+            (true, OpenContour::Outline) => c.outline_contour(offset_amount, options),
+            (true, _) => {
+                offset_contours.push(c);
+                continue;
+            }
+            (false, _) => c.offset_contour(amount, options),
+        };
+        match offset {
+            Ok(offset) => {
+                offset_contours.extend(offset.contours);
+                markers.extend(offset.markers);
+                report.disappeared_features.extend(offset.disappeared);
+                report.warnings.extend(offset.warnings);
+            }
+            // a contour that is cut at its original size makes a part that is the wrong size, so it is either listed
+            // where it can be seen or stops the whole drawing from being offset
+            Err(error) => refuse_or_report_contour(
+                c.source_entities(),
+                error,
+                options,
+                &mut report,
+                &mut unchanged,
+            )?,
+        }
+    }
+    // the header, layers, etc of the original drawing are kept, so that it is read back in the same way
+    let mut new_drawing = offset_contours.to_dxf_in(drawing);
+    new_drawing.entities.extend(unchanged);
+    new_drawing.entities.extend(markers);
+    // entities that are not cut go after the ones that are, in the order they were in. The cut entities have been
+    // joined up and offset, so there is no telling where in between them an entity that is not cut used to be.
+    new_drawing.entities.extend(passed_through);
+    clear_reused_handles(&mut new_drawing.entities, &insert_handles);
 
     // return the new dxf
     let mut ret = Vec::new();
-    new_drawing.save(&mut ret).unwrap();
-    return ret;
+    new_drawing
+        .save(&mut ret)
+        .map_err(|e| KerfAdjustmentError::from(KerfAdjustmentErrorReason::CannotWriteDxf(e.to_string())))?;
+    Ok(OffsetResult::new(ret, report))
 }
@@ -0,0 +1,61 @@
+use crate::contour::Contour;
+
+/// For each contour, counts how many of the other closed contours it lies inside of
+///
+/// A depth of 0 is the outer boundary of a part, 1 is a hole in that part, 2 is an island inside of that hole, etc.
+/// Open contours do not enclose anything, so they never add to the depth of other contours.
+pub fn nesting_depths(contours: &[Contour]) -> Vec<usize> {
+    contours
+        .iter()
+        .enumerate()
+        .map(|(i, contour)| match contour.point_on_contour() {
+            Some(pt) => contours
+                .iter()
+                .enumerate()
+                .filter(|(j, other)| i != *j && other.contains_point(&pt))
+                .count(),
+            None => 0,
+        })
+        .collect()
+}
+
+/// Whether a contour at the given nesting depth is a hole, i.e whether the material is on the outside of it
+pub fn is_hole(depth: usize) -> bool {
+    depth % 2 == 1
+}
+
+#[cfg(test)]
+mod nesting_test {
+    use crate::contour::Contour;
+    use crate::nesting::{is_hole, nesting_depths};
+    use dxf::entities::*;
+    use dxf::Point;
+
+    fn circle(x: f64, y: f64, radius: f64) -> Contour {
+        Contour::create_from_entity(Entity::new(EntityType::Circle(Circle::new(
+            Point::new(x, y, 0.),
+            radius,
+        ))))
+    }
+
+    #[test]
+    pub fn test_nested_circles() {
+        // given: a part with a hole in it, an island inside of the hole, and a separate part off to the side
+        let contours = vec![
+            circle(0., 0., 1.),
+            circle(0., 0., 10.),
+            circle(0., 0., 5.),
+            circle(30., 0., 5.),
+        ];
+
+        // when: we work out how they are nested
+        let depths = nesting_depths(&contours);
+
+        // then: the outer boundaries are not holes, and the hole is a hole
+        assert_eq!(depths, vec![2, 0, 1, 0]);
+        assert!(!is_hole(depths[0]));
+        assert!(!is_hole(depths[1]));
+        assert!(is_hole(depths[2]));
+        assert!(!is_hole(depths[3]));
+    }
+}
@@ -0,0 +1,196 @@
+//! Conversion from object coordinates (OCS) to world coordinates (WCS)
+//!
+//! Flat entities like arcs, circles and polylines are stored in a coordinate system that depends on their extrusion
+//! normal. Parts that are mirrored in CAD commonly end up with a normal of (0, 0, -1), which makes their x axis point
+//! the other way. Entities are moved into world coordinates with a normal of (0, 0, 1) before anything else is done
+//! with them, so that the rest of the code can treat the drawing as being in the XY plane.
+use crate::contour::VectorWrapper;
+use dxf::entities::*;
+use dxf::{Point, Vector};
+use nalgebra::Vector3;
+
+/// Normals whose x and y components are both smaller than this use the world y axis to build their x axis
+const ARBITRARY_AXIS_THRESHOLD: f64 = 1. / 64.;
+
+/// How far a normal can lean away from the z axis while still counting as pointing along it
+const NORMAL_TOLERANCE: f64 = 1e-9;
+
+/// The x and y axes of the object coordinate system for the given normal, using the DXF Arbitrary Axis Algorithm
+pub fn arbitrary_axes(normal: &Vector3<f64>) -> (Vector3<f64>, Vector3<f64>) {
+    let normal = normal.normalize();
+    let x_axis =
+        if normal.x.abs() < ARBITRARY_AXIS_THRESHOLD && normal.y.abs() < ARBITRARY_AXIS_THRESHOLD {
+            Vector3::y().cross(&normal)
+        } else {
+            Vector3::z().cross(&normal)
+        }
+        .normalize();
+    let y_axis = normal.cross(&x_axis).normalize();
+    (x_axis, y_axis)
+}
+
+/// Converts a point from the object coordinate system of the given normal to world coordinates
+pub fn ocs_to_wcs(normal: &Vector3<f64>, pt: &Vector3<f64>) -> Vector3<f64> {
+    let (x_axis, y_axis) = arbitrary_axes(normal);
+    x_axis * pt.x + y_axis * pt.y + normal.normalize() * pt.z
+}
+
+/// For normals along the z axis, returns 1 if the normal points up and -1 if it points down.
+/// Entities with any other normal are not flat in the XY plane, so this returns `None` for them.
+fn z_direction(normal: &Vector) -> Option<f64> {
+    let VectorWrapper(normal) = normal.clone().into();
+    let normal = normal.try_normalize(0.)?;
+    if normal.x.abs() < NORMAL_TOLERANCE && normal.y.abs() < NORMAL_TOLERANCE {
+        Some(normal.z.signum())
+    } else {
+        None
+    }
+}
+
+/// Whether an entity with this normal lies flat in (or parallel to) the XY plane
+pub fn is_flat(normal: &Vector) -> bool {
+    z_direction(normal).is_some()
+}
+
+/// Same as `ocs_to_wcs`, for the normal of a DXF entity
+pub fn vector_to_wcs(normal: &Vector, pt: &Vector3<f64>) -> Vector3<f64> {
+    let VectorWrapper(normal) = normal.clone().into();
+    ocs_to_wcs(&normal, pt)
+}
+
+fn point_to_wcs(normal: &Vector, pt: &Point) -> Point {
+    let VectorWrapper(normal) = normal.clone().into();
+    let VectorWrapper(pt) = pt.clone().into();
+    VectorWrapper(ocs_to_wcs(&normal, &pt)).into()
+}
+
+/// Rewrites an arc in world coordinates, with its normal pointing up. Returns `None` if the arc is not parallel to
+/// the XY plane.
+pub fn arc_to_wcs(arc: &Arc) -> Option<Arc> {
+    let direction = z_direction(&arc.normal)?;
+    if direction > 0. {
+        return Some(Arc {
+            center: point_to_wcs(&arc.normal, &arc.center),
+            normal: Vector::z_axis(),
+            ..arc.clone()
+        });
+    }
+
+    // Looking from below flips the x axis, and turns the counter-clockwise arc into a clockwise one. The end of the
+    // arc becomes its start, so that it still goes counter-clockwise.
+    let mirror_angle = |angle: f64| (180. - angle).rem_euclid(360.);
+    Some(Arc {
+        center: point_to_wcs(&arc.normal, &arc.center),
+        normal: Vector::z_axis(),
+        start_angle: mirror_angle(arc.end_angle),
+        end_angle: mirror_angle(arc.start_angle),
+        ..arc.clone()
+    })
+}
+
+/// Rewrites an entity in world coordinates, with its normal pointing up
+///
+/// Entities that are not parallel to the XY plane, or that are already in world coordinates, are returned as they
+/// are.
+pub fn entity_to_wcs(e: Entity) -> Entity {
+    let specific = match &e.specific {
+        EntityType::Arc(arc) => arc_to_wcs(arc).map(EntityType::Arc),
+        EntityType::Circle(circle) => z_direction(&circle.normal).map(|_| {
+            EntityType::Circle(Circle {
+                center: point_to_wcs(&circle.normal, &circle.center),
+                normal: Vector::z_axis(),
+                ..circle.clone()
+            })
+        }),
+        // Line end points are always in world coordinates. Only the direction of its thickness depends on the normal.
+        EntityType::Line(line) => z_direction(&line.extrusion_direction).map(|_| {
+            EntityType::Line(Line {
+                extrusion_direction: Vector::z_axis(),
+                ..line.clone()
+            })
+        }),
+        EntityType::LwPolyline(poly) => z_direction(&poly.extrusion_direction).map(|direction| {
+            let mut poly = poly.clone();
+            for v in poly.vertices.iter_mut() {
+                let pt = point_to_wcs(&poly.extrusion_direction, &Point::new(v.x, v.y, 0.));
+                v.x = pt.x;
+                v.y = pt.y;
+                // Counter-clockwise bulges turn clockwise when seen from the other side
+                v.bulge *= direction;
+            }
+            poly.extrusion_direction = Vector::z_axis();
+            EntityType::LwPolyline(poly)
+        }),
+        EntityType::Polyline(poly) => z_direction(&poly.normal).map(|direction| {
+            let mut poly = poly.clone();
+            for v in poly.vertices.iter_mut() {
+                v.location = point_to_wcs(&poly.normal, &v.location);
+                v.bulge *= direction;
+            }
+            poly.location = point_to_wcs(&poly.normal, &poly.location);
+            poly.normal = Vector::z_axis();
+            EntityType::Polyline(poly)
+        }),
+        _ => None,
+    };
+
+    match specific {
+        Some(specific) => Entity {
+            common: e.common,
+            specific,
+        },
+        None => e,
+    }
+}
+
+#[cfg(test)]
+mod ocs_test {
+    use crate::contour::open_ends_of_entity;
+    use crate::ocs::{arbitrary_axes, entity_to_wcs};
+    use dxf::entities::*;
+    use dxf::{Point, Vector};
+    use nalgebra::Vector3;
+
+    const EPSILON: f64 = 1e-9;
+
+    #[test]
+    pub fn test_arbitrary_axes() {
+        // given: a normal pointing down, and one lying along the x axis
+        let (down, sideways) = (Vector3::new(0., 0., -1.), Vector3::new(1., 0., 0.));
+
+        // when: we work out their object coordinate systems
+        let (down_x, down_y) = arbitrary_axes(&down);
+        let (sideways_x, sideways_y) = arbitrary_axes(&sideways);
+
+        // then: they match the ones from the DXF reference
+        assert!((down_x - Vector3::new(-1., 0., 0.)).magnitude() < EPSILON);
+        assert!((down_y - Vector3::new(0., 1., 0.)).magnitude() < EPSILON);
+        assert!((sideways_x - Vector3::new(0., 1., 0.)).magnitude() < EPSILON);
+        assert!((sideways_y - Vector3::new(0., 0., 1.)).magnitude() < EPSILON);
+    }
+
+    #[test]
+    pub fn test_mirrored_arc() {
+        // given: a quarter circle around (2, 0) in object coordinates, seen from below
+        let arc = Arc {
+            center: Point::new(2., 0., 0.),
+            radius: 1.,
+            normal: Vector::new(0., 0., -1.),
+            start_angle: 0.,
+            end_angle: 90.,
+            ..Default::default()
+        };
+
+        // when: we move it into world coordinates
+        let world = entity_to_wcs(Entity::new(EntityType::Arc(arc)));
+
+        // then: it is mirrored across the y axis, and still goes through the same points
+        let (start, end) = open_ends_of_entity(&world).unwrap();
+        assert!((start - Vector3::new(-2., 1., 0.)).magnitude() < EPSILON);
+        assert!((end - Vector3::new(-3., 0., 0.)).magnitude() < EPSILON);
+        match world.specific {
+            EntityType::Arc(arc) => assert_eq!(arc.normal, Vector::z_axis()),
+            _ => panic!("arc should still be an arc"),
+        }
+    }
+}
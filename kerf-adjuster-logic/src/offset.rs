@@ -0,0 +1,825 @@
+use crate::cleanup::remove_self_intersections;
+use crate::contour::{Contour, OffsetContours, VectorWrapper};
+use crate::errors::{KerfAdjustmentError, KerfAdjustmentErrorReason};
+use crate::geometry::*;
+use crate::options::{CollapsedCircle, CornerJoin, EndCap, OffsetOptions};
+use crate::report::{DisappearedFeature, OffsetWarning, WarningKind};
+use crate::segment::{ContourSegment, Segment};
+use dxf::entities::*;
+use nalgebra::Vector3;
+use std::rc::Rc;
+
+/// One piece of a contour that is being offset, along with the entity it came from
+#[derive(Clone)]
+pub(crate) struct Piece {
+    pub(crate) shape: Segment,
+    /// The entity that the piece (or the piece that it was offset from) came from. Pieces that were added to fill in
+    /// a corner come from the same entity as the piece before them.
+    pub(crate) source: Rc<Entity>,
+    /// For offset pieces, the index of the original piece that it was offset from. Pieces that were added to fill in
+    /// a corner do not have one.
+    pub(crate) original: Option<usize>,
+    /// How close the piece is meant to stay to the original contour. Anything that comes closer than this is part of
+    /// a loop that the offset contour made by folding back over itself.
+    pub(crate) clearance: f64,
+}
+
+/// How two neighbouring offset pieces get connected to each other
+#[derive(Default)]
+struct Join {
+    /// New end point for the piece before the corner
+    new_end: Option<Vector3<f64>>,
+    /// New start point for the piece after the corner
+    new_start: Option<Vector3<f64>>,
+    /// Pieces that go in between the two, to bridge the gap between them
+    bridge: Vec<Segment>,
+    /// How close the bridge pieces are meant to stay to the original contour
+    bridge_clearance: f64,
+}
+
+/// Two neighbouring pieces that meet at a corner, before and after being offset
+struct Corner<'a> {
+    before: &'a Segment,
+    after: &'a Segment,
+    offset_before: &'a Segment,
+    offset_after: &'a Segment,
+}
+
+/// Works out how to connect two neighbouring pieces after they have been offset by `distance` to one side. Gaps are
+/// filled in according to `corner_join`.
+fn join_offset_pieces(
+    corner: Corner,
+    offset_left: bool,
+    distance: f64,
+    corner_join: CornerJoin,
+    options: &OffsetOptions,
+) -> Join {
+    let Corner {
+        before,
+        after,
+        offset_before,
+        offset_after,
+    } = corner;
+    let (gap_start, gap_end) = (offset_before.end(), offset_after.start());
+    if (gap_start - gap_end).magnitude() < options.join_tolerance {
+        // The pieces meet smoothly (e.g. an arc tangent to a line), so they still meet after offsetting. Any small
+        // gap that was left between them in the drawing is closed up.
+        let meeting_point = (gap_start + gap_end) / 2.;
+        return Join {
+            new_end: Some(meeting_point),
+            new_start: Some(meeting_point),
+            ..Default::default()
+        };
+    }
+
+    let corner = before.end();
+    let (tangent_in, tangent_out) = (before.end_tangent(), after.start_tangent());
+    let turn = cross_z(&tangent_in, &tangent_out);
+
+    // If the contour turns away from the side it's offset to, the offset pieces move apart and leave a gap.
+    // Otherwise, they move towards each other and overlap.
+    let opens_gap = if turn.abs() < 1e-9 {
+        tangent_in.dot(&tangent_out) < 0.
+    } else {
+        (turn < 0.) == offset_left
+    };
+
+    if !opens_gap {
+        // Trim both pieces back to where they cross, using the crossing closest to the original corner
+        let crossing = offset_before
+            .intersections(offset_after)
+            .into_iter()
+            .min_by(|a, b| (a - corner).magnitude().total_cmp(&(b - corner).magnitude()));
+
+        return match crossing {
+            Some(pt) => Join {
+                new_end: Some(pt),
+                new_start: Some(pt),
+                ..Default::default()
+            },
+            // The pieces do not cross, so go through the original corner instead. This makes a small loop, which
+            // gets cleaned up once the whole contour has been offset.
+            None => Join {
+                new_end: None,
+                new_start: None,
+                bridge: vec![
+                    Segment::Line {
+                        start: gap_start,
+                        end: corner,
+                    },
+                    Segment::Line {
+                        start: corner,
+                        end: gap_end,
+                    },
+                ],
+                bridge_clearance: distance,
+            },
+        };
+    }
+
+    match corner_join {
+        CornerJoin::Round => {
+            // When the contour doubles back on itself, go around the corner on the side we are offsetting to
+            let ccw = if turn.abs() < 1e-9 {
+                !offset_left
+            } else {
+                turn > 0.
+            };
+            Join {
+                new_end: None,
+                new_start: None,
+                bridge: vec![Segment::Arc {
+                    center: corner,
+                    radius: distance,
+                    start_angle: angle_on_circle(&corner, &gap_start),
+                    end_angle: angle_on_circle(&corner, &gap_end),
+                    ccw,
+                }],
+                bridge_clearance: distance,
+            }
+        }
+        CornerJoin::Miter => {
+            let miter_tip = line_line_intersection(&gap_start, &tangent_in, &gap_end, &tangent_out)
+                .filter(|tip| (tip - corner).magnitude() <= options.miter_limit * distance);
+
+            match miter_tip {
+                Some(tip) => {
+                    // Lines can just be extended to the tip, but arcs need a straight extension added on
+                    let mut join = Join {
+                        bridge_clearance: distance,
+                        ..Default::default()
+                    };
+                    match offset_before {
+                        Segment::Line { .. } => join.new_end = Some(tip),
+                        _ => join.bridge.push(Segment::Line {
+                            start: gap_start,
+                            end: tip,
+                        }),
+                    }
+                    match offset_after {
+                        Segment::Line { .. } => join.new_start = Some(tip),
+                        _ => join.bridge.push(Segment::Line {
+                            start: tip,
+                            end: gap_end,
+                        }),
+                    }
+                    join
+                }
+                // The corner is too sharp, so cut it off with a bevel
+                None => Join {
+                    new_end: None,
+                    new_start: None,
+                    bridge: vec![Segment::Line {
+                        start: gap_start,
+                        end: gap_end,
+                    }],
+                    // The middle of the bevel is closer to the corner than the ends of it are
+                    bridge_clearance: distance_to_segment(&corner, &gap_start, &gap_end),
+                },
+            }
+        }
+    }
+}
+
+/// The segments of a closed contour, as pieces to be offset
+fn contour_pieces(contour: &Contour) -> Vec<Piece> {
+    contour
+        .segments
+        .iter()
+        .enumerate()
+        .map(|(i, segment)| Piece {
+            shape: segment.shape.clone(),
+            source: segment.source.clone(),
+            original: Some(i),
+            clearance: 0.,
+        })
+        .collect()
+}
+
+/// Offsets a closed contour, joining the offset pieces back together at the corners.
+///
+/// Loops where the offset contour folds back over itself are removed, which can split it into several contours or
+/// make it disappear entirely.
+///
+/// Negative amount will shrink the area of the contour. Positive amount will grow the area of the contour.
+///
+/// The join tolerance in `options` is taken to be in drawing units, so it has to have been worked out for the whole
+/// drawing already (see `OffsetOptions::for_extents`).
+pub(crate) fn offset_closed_contour(
+    contour: &Contour,
+    amount: f64,
+    options: &OffsetOptions,
+) -> Result<OffsetContours, KerfAdjustmentError> {
+    let pieces = contour_pieces(contour);
+    if pieces.is_empty() {
+        return Err(KerfAdjustmentErrorReason::CannotOffsetEmptyContour.into());
+    }
+
+    let distance = amount.abs();
+
+    // The contour is walked in one consistent direction, so the side we want is on the same side of every piece. The
+    // inside of a counter-clockwise contour is on its left, so growing it moves every piece to the right, and
+    // shrinking it moves every piece to the left. It is the other way around for a clockwise contour.
+    let is_counter_clockwise = contour.signed_area() >= 0.;
+    let offset_left = is_counter_clockwise == (amount < 0.);
+    offset_pieces(pieces, offset_left, distance, options, |_| options.corner_join)
+}
+
+/// Replaces an open contour with a closed outline around it, offset by `distance` on both sides, like the slot that
+/// gets cut when the laser follows the contour
+///
+/// The outline is made by offsetting the contour walked there and back again, so the two ends are corners where the
+/// contour doubles back on itself. They are joined according to `options.end_cap`, and every other corner according
+/// to `options.corner_join`.
+pub(crate) fn outline_open_contour(
+    contour: &Contour,
+    distance: f64,
+    options: &OffsetOptions,
+) -> Result<OffsetContours, KerfAdjustmentError> {
+    let there = contour_pieces(contour);
+    if there.is_empty() {
+        return Err(KerfAdjustmentErrorReason::CannotOffsetEmptyContour.into());
+    }
+
+    // An outline with no width would just be the contour again
+    let distance = distance.abs();
+    if distance < options.join_tolerance {
+        return Ok(OffsetContours {
+            contours: vec![contour.clone()],
+            markers: vec![],
+            disappeared: vec![],
+            warnings: vec![],
+        });
+    }
+
+    let end_count = there.len();
+    let back = contour_pieces(&contour.clone().reversed())
+        .into_iter()
+        .map(|piece| Piece {
+            original: piece.original.map(|i| i + end_count),
+            ..piece
+        });
+    let pieces = there.into_iter().chain(back).collect::<Vec<_>>();
+
+    // Both offset pieces at an end are parallel to each other, so a miter never finds a tip and always falls back to
+    // a straight bevel across the end
+    let end_join = match options.end_cap {
+        EndCap::Round => CornerJoin::Round,
+        EndCap::Flat => CornerJoin::Miter,
+    };
+    let is_end = |i: usize| i == end_count - 1 || i == 2 * end_count - 1;
+    offset_pieces(pieces, true, distance, options, |i| {
+        if is_end(i) {
+            end_join
+        } else {
+            options.corner_join
+        }
+    })
+}
+
+/// Offsets pieces that make up a closed loop by `distance` to one side, and joins them back together. The corner
+/// after piece `i` is joined according to `corner_join_at(i)`.
+fn offset_pieces(
+    pieces: Vec<Piece>,
+    offset_left: bool,
+    distance: f64,
+    options: &OffsetOptions,
+    corner_join_at: impl Fn(usize) -> CornerJoin,
+) -> Result<OffsetContours, KerfAdjustmentError> {
+    let left_distance = if offset_left { distance } else { -distance };
+
+    // Arcs that shrink past their center fold the contour back over itself, and get cleaned up along with the
+    // rest of the loop that they are in
+    let warnings = pieces
+        .iter()
+        .filter(|piece| piece.shape.collapses_when_offset(left_distance))
+        .map(|piece| {
+            let kind = match piece.shape {
+                Segment::Circle { .. } => WarningKind::CollapsedCircle,
+                _ => WarningKind::CollapsedArc,
+            };
+            OffsetWarning::new(kind, &piece.source.common)
+        })
+        .collect::<Vec<_>>();
+
+    // Only circles can shrink down to nothing, and they are a whole contour on their own
+    let offset_pieces = match pieces
+        .iter()
+        .map(|piece| piece.shape.offset(left_distance))
+        .collect::<Option<Vec<_>>>()
+    {
+        Some(offset_pieces) => offset_pieces,
+        None => return Ok(collapse_circle(&pieces, warnings, options)),
+    };
+
+    // Work out how each piece connects to the one after it
+    let piece_count = pieces.len();
+    let joins = if piece_count > 1 {
+        (0..piece_count)
+            .map(|i| {
+                let next = (i + 1) % piece_count;
+                let corner = Corner {
+                    before: &pieces[i].shape,
+                    after: &pieces[next].shape,
+                    offset_before: &offset_pieces[i],
+                    offset_after: &offset_pieces[next],
+                };
+                join_offset_pieces(
+                    corner,
+                    offset_left,
+                    distance,
+                    corner_join_at(i),
+                    options,
+                )
+            })
+            .collect::<Vec<_>>()
+    } else {
+        vec![Join::default()]
+    };
+
+    let mut joined_pieces = Vec::with_capacity(piece_count);
+    for (i, (piece, offset_piece)) in pieces.iter().zip(offset_pieces).enumerate() {
+        let previous_join = &joins[(i + piece_count - 1) % piece_count];
+        let mut shape = offset_piece;
+        if let Some(start) = previous_join.new_start {
+            shape = shape.with_start(start);
+        }
+        if let Some(end) = joins[i].new_end {
+            shape = shape.with_end(end);
+        }
+
+        if !shape.is_empty() {
+            joined_pieces.push(Piece {
+                shape,
+                source: piece.source.clone(),
+                original: Some(i),
+                clearance: distance,
+            });
+        }
+
+        joined_pieces.extend(joins[i].bridge.iter().cloned().map(|bridge| Piece {
+            shape: bridge,
+            source: piece.source.clone(),
+            original: None,
+            clearance: joins[i].bridge_clearance,
+        }));
+    }
+
+    // Features that are too small for the offset make the contour fold back over itself
+    let loops = remove_self_intersections(&pieces, joined_pieces, options.join_tolerance)
+        .ok_or_else(|| KerfAdjustmentError {
+            reason: KerfAdjustmentErrorReason::CannotConnectContourAfterAdjustment,
+            entity: pieces.first().map(|piece| Box::new(piece.source.common.clone())),
+        })?;
+    let disappeared = disappeared_features(&pieces, &loops);
+
+    Ok(OffsetContours {
+        contours: loops
+            .into_iter()
+            .map(|offset_loop| Contour {
+                segments: offset_loop
+                    .into_iter()
+                    .map(|piece| ContourSegment {
+                        shape: piece.shape,
+                        source: piece.source,
+                    })
+                    .collect(),
+                end_points: None,
+            })
+            .collect(),
+        markers: vec![],
+        disappeared,
+        warnings,
+    })
+}
+
+/// Deals with a circle that shrinks down to nothing, according to `options.collapsed_circle`
+fn collapse_circle(
+    pieces: &[Piece],
+    warnings: Vec<OffsetWarning>,
+    options: &OffsetOptions,
+) -> OffsetContours {
+    match options.collapsed_circle {
+        CollapsedCircle::Drop => OffsetContours {
+            contours: vec![],
+            markers: vec![],
+            disappeared: disappeared_features(pieces, &[]),
+            warnings,
+        },
+        CollapsedCircle::Point => OffsetContours {
+            contours: vec![],
+            markers: pieces
+                .iter()
+                .filter_map(|piece| match piece.shape {
+                    Segment::Circle { center, .. } => Some(Entity {
+                        common: piece.source.common.clone(),
+                        specific: EntityType::ModelPoint(ModelPoint::new(
+                            VectorWrapper(center).into(),
+                        )),
+                    }),
+                    _ => None,
+                })
+                .collect(),
+            disappeared: vec![],
+            warnings,
+        },
+    }
+}
+
+/// The original pieces that nothing is left of in the offset contour
+fn disappeared_features(original: &[Piece], loops: &[Vec<Piece>]) -> Vec<DisappearedFeature> {
+    original
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !loops.iter().flatten().any(|piece| piece.original == Some(*i)))
+        .map(|(_, piece)| DisappearedFeature::new(&piece.source.common, &piece.shape.midpoint().0))
+        .collect()
+}
+
+#[cfg(test)]
+mod offset_test {
+    use crate::contour::{open_ends_of_entity, Contour, OffsetContours};
+    use crate::options::{CornerJoin, EndCap, OffsetOptions};
+    use dxf::entities::*;
+    use dxf::{LwPolylineVertex, Point};
+    use nalgebra::Vector3;
+
+    const EPSILON: f64 = 1e-6;
+
+    /// Makes a closed contour out of lines between the points, with every other line pointing backwards
+    fn polygon(points: &[(f64, f64)]) -> Contour {
+        let lines = points
+            .iter()
+            .zip(points.iter().cycle().skip(1))
+            .enumerate()
+            .map(|(i, (&(x1, y1), &(x2, y2)))| {
+                let (p1, p2) = (Point::new(x1, y1, 0.), Point::new(x2, y2, 0.));
+                let line = if i % 2 == 0 {
+                    Line::new(p1, p2)
+                } else {
+                    Line::new(p2, p1)
+                };
+                Contour::from(Entity::new(EntityType::Line(line)))
+            });
+
+        let mut contour: Option<Contour> = None;
+        for line in lines {
+            contour = Some(match contour {
+                None => line,
+                Some(c) => c.combine_attempt(line, Contour::EPSILON).unwrap(),
+            });
+        }
+        let contour = contour.unwrap();
+        assert!(!contour.is_open());
+        contour
+    }
+
+    fn options(corner_join: CornerJoin) -> OffsetOptions {
+        OffsetOptions {
+            corner_join,
+            ..Default::default()
+        }
+    }
+
+    /// The contour that offsetting made, when it is not expected to split up or disappear
+    fn only_contour(offset: OffsetContours) -> Contour {
+        assert_eq!(offset.contours.len(), 1);
+        assert!(offset.disappeared.is_empty());
+        offset.contours.into_iter().next().unwrap()
+    }
+
+    fn endpoints(c: &Contour) -> Vec<Vector3<f64>> {
+        c.to_entities()
+            .iter()
+            .filter_map(open_ends_of_entity)
+            .flat_map(|(start, end)| vec![start, end])
+            .collect()
+    }
+
+    fn has_point(c: &Contour, x: f64, y: f64) -> bool {
+        endpoints(c)
+            .iter()
+            .any(|pt| (pt - Vector3::new(x, y, 0.)).magnitude() < EPSILON)
+    }
+
+    /// An L-shaped open contour, going along the x axis and then up
+    fn open_corner() -> Contour {
+        let line = |(x1, y1), (x2, y2)| {
+            let line = Line::new(Point::new(x1, y1, 0.), Point::new(x2, y2, 0.));
+            Contour::from(Entity::new(EntityType::Line(line)))
+        };
+        let contour = line((0., 0.), (10., 0.))
+            .combine_attempt(line((10., 0.), (10., 10.)), Contour::EPSILON)
+            .unwrap();
+        assert!(contour.is_open());
+        contour
+    }
+
+    const SQUARE: [(f64, f64); 4] = [(0., 0.), (10., 0.), (10., 10.), (0., 10.)];
+
+    #[test]
+    pub fn test_mitered_square_grows() {
+        // given: a square
+        let square = polygon(&SQUARE);
+
+        // when: we grow it with mitered corners
+        let offset = only_contour(
+            square
+                .offset_contour(1., &options(CornerJoin::Miter))
+                .unwrap(),
+        );
+
+        // then: it is a bigger square
+        assert_eq!(offset.to_entities().len(), 4);
+        for pt in endpoints(&offset) {
+            assert!((pt.x + 1.).abs() < EPSILON || (pt.x - 11.).abs() < EPSILON);
+            assert!((pt.y + 1.).abs() < EPSILON || (pt.y - 11.).abs() < EPSILON);
+        }
+    }
+
+    #[test]
+    pub fn test_clockwise_square_grows_the_same_way() {
+        // given: a square that goes around clockwise, and the same square going around counter-clockwise
+        let square = polygon(&SQUARE);
+        let (clockwise, counter_clockwise) = if square.signed_area() < 0. {
+            (square.clone(), square.reversed())
+        } else {
+            (square.clone().reversed(), square)
+        };
+        assert!(clockwise.signed_area() < 0. && counter_clockwise.signed_area() > 0.);
+
+        // when: we grow and shrink both of them
+        let grown = |c: &Contour| only_contour(c.offset_contour(1., &options(CornerJoin::Miter)).unwrap());
+        let shrunk = |c: &Contour| only_contour(c.offset_contour(-1., &options(CornerJoin::Miter)).unwrap());
+
+        // then: the way they go around makes no difference to which way they are offset
+        for c in [&clockwise, &counter_clockwise] {
+            assert!((grown(c).signed_area().abs() - 144.).abs() < EPSILON);
+            assert!((shrunk(c).signed_area().abs() - 64.).abs() < EPSILON);
+        }
+    }
+
+    #[test]
+    pub fn test_mitered_square_shrinks() {
+        // given: a square
+        let square = polygon(&SQUARE);
+
+        // when: we shrink it
+        let offset = only_contour(
+            square
+                .offset_contour(-1., &options(CornerJoin::Miter))
+                .unwrap(),
+        );
+
+        // then: it is a smaller square
+        assert_eq!(offset.to_entities().len(), 4);
+        for pt in endpoints(&offset) {
+            assert!((pt.x - 1.).abs() < EPSILON || (pt.x - 9.).abs() < EPSILON);
+            assert!((pt.y - 1.).abs() < EPSILON || (pt.y - 9.).abs() < EPSILON);
+        }
+    }
+
+    #[test]
+    pub fn test_round_corners_fill_gaps_with_arcs() {
+        // given: a square
+        let square = polygon(&SQUARE);
+
+        // when: we grow it with round corners
+        let offset = only_contour(
+            square
+                .offset_contour(1., &options(CornerJoin::Round))
+                .unwrap(),
+        );
+
+        // then: each corner gets a quarter circle around the original corner
+        let arcs = offset
+            .to_entities()
+            .iter()
+            .filter_map(|e| match &e.specific {
+                EntityType::Arc(arc) => Some(arc.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(offset.to_entities().len(), 8);
+        assert_eq!(arcs.len(), 4);
+        for arc in arcs {
+            assert!((arc.radius - 1.).abs() < EPSILON);
+            let sweep = (arc.end_angle - arc.start_angle).rem_euclid(360.);
+            assert!((sweep - 90.).abs() < EPSILON);
+        }
+        assert!(has_point(&offset, 10., -1.));
+        assert!(has_point(&offset, 11., 0.));
+    }
+
+    #[test]
+    pub fn test_concave_corner_is_trimmed() {
+        // given: an L shape, which has one concave corner at (5, 5)
+        let l_shape = polygon(&[
+            (0., 0.),
+            (10., 0.),
+            (10., 5.),
+            (5., 5.),
+            (5., 10.),
+            (0., 10.),
+        ]);
+
+        // when: we grow it
+        let offset = only_contour(
+            l_shape
+                .offset_contour(1., &options(CornerJoin::Round))
+                .unwrap(),
+        );
+
+        // then: the lines on either side of the concave corner are trimmed to meet each other
+        assert!(has_point(&offset, 6., 6.));
+        assert_eq!(offset.to_entities().len(), 6 + 5);
+    }
+
+    #[test]
+    pub fn test_sharp_miter_is_beveled() {
+        // given: a long thin triangle with a very sharp corner at (100, 0)
+        let triangle = polygon(&[(0., 0.), (100., 0.), (0., 5.)]);
+
+        // when: we grow it with mitered corners
+        let offset = only_contour(
+            triangle
+                .offset_contour(1., &options(CornerJoin::Miter))
+                .unwrap(),
+        );
+
+        // then: the sharp corner gets a bevel instead of a long spike
+        assert_eq!(offset.to_entities().len(), 4);
+        for pt in endpoints(&offset) {
+            assert!(pt.x < 100. + 4.);
+        }
+    }
+
+    #[test]
+    pub fn test_lwpolyline_stays_lwpolyline() {
+        // given: a closed polyline in the shape of a slot with rounded ends
+        let vertex = |x, y, bulge| LwPolylineVertex {
+            x,
+            y,
+            bulge,
+            ..Default::default()
+        };
+        let mut slot = LwPolyline {
+            vertices: vec![
+                vertex(0., 0., 0.),
+                vertex(10., 0., 1.),
+                vertex(10., 2., 0.),
+                vertex(0., 2., 1.),
+            ],
+            ..Default::default()
+        };
+        slot.set_is_closed(true);
+        let contour = Contour::from(Entity::new(EntityType::LwPolyline(slot)));
+        assert!(!contour.is_open());
+
+        // when: we grow it
+        let offset = only_contour(
+            contour
+                .offset_contour(1., &options(CornerJoin::Round))
+                .unwrap(),
+        );
+
+        // then: we get back a single polyline for a wider slot
+        assert_eq!(offset.to_entities().len(), 1);
+        let poly = match &offset.to_entities()[0].specific {
+            EntityType::LwPolyline(poly) => poly.clone(),
+            _ => panic!("offset slot should be a polyline"),
+        };
+        assert!(poly.get_is_closed());
+        let expected = [(0., -1., 0.), (10., -1., 1.), (10., 3., 0.), (0., 3., 1.)];
+        assert_eq!(poly.vertices.len(), expected.len());
+        for (v, (x, y, bulge)) in poly.vertices.iter().zip(expected.iter()) {
+            assert!((v.x - x).abs() < EPSILON, "{} != {}", v.x, x);
+            assert!((v.y - y).abs() < EPSILON, "{} != {}", v.y, y);
+            assert!((v.bulge - bulge).abs() < EPSILON);
+        }
+    }
+
+    #[test]
+    pub fn test_narrow_notch_fills_in() {
+        // given: a square with a notch cut into its top that is 2 wide and 5 deep
+        let notched = polygon(&[
+            (0., 0.),
+            (20., 0.),
+            (20., 20.),
+            (11., 20.),
+            (11., 15.),
+            (9., 15.),
+            (9., 20.),
+            (0., 20.),
+        ]);
+
+        // when: we grow it by more than half the width of the notch
+        let offset = notched
+            .offset_contour(1.5, &options(CornerJoin::Round))
+            .unwrap();
+
+        // then: the notch is gone, leaving just a dip where the corners of the notch were rounded off
+        assert_eq!(offset.contours.len(), 1);
+        for pt in endpoints(&offset.contours[0]) {
+            assert!(pt.y > 21. || pt.x < 9. || pt.x > 11.);
+        }
+
+        // and: the sides and bottom of the notch are reported as having disappeared
+        let mut disappeared = offset
+            .disappeared
+            .iter()
+            .map(|feature| (feature.x, feature.y))
+            .collect::<Vec<_>>();
+        disappeared.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+        assert_eq!(disappeared, vec![(9., 17.5), (10., 15.), (11., 17.5)]);
+    }
+
+    #[test]
+    pub fn test_narrow_neck_splits_contour() {
+        // given: two squares joined together by a neck that is 2 wide
+        let dumbbell = polygon(&[
+            (0., 0.),
+            (10., 0.),
+            (10., 4.),
+            (12., 4.),
+            (12., 0.),
+            (22., 0.),
+            (22., 10.),
+            (12., 10.),
+            (12., 6.),
+            (10., 6.),
+            (10., 10.),
+            (0., 10.),
+        ]);
+
+        // when: we shrink it by more than half the width of the neck
+        let offset = dumbbell
+            .offset_contour(-1.5, &options(CornerJoin::Miter))
+            .unwrap();
+
+        // then: the neck is pinched off, leaving a smaller square on each side
+        assert_eq!(offset.contours.len(), 2);
+        for contour in offset.contours.iter() {
+            let points = endpoints(contour);
+            let left = points.iter().all(|pt| pt.x < 11.);
+            let (min_x, max_x) = if left { (1.5, 8.5) } else { (13.5, 20.5) };
+            for pt in points {
+                let on_side = (pt.x - min_x).abs() < EPSILON || (pt.x - max_x).abs() < EPSILON;
+                let on_top_or_bottom = (pt.y - 1.5).abs() < EPSILON || (pt.y - 8.5).abs() < EPSILON;
+                assert!(on_side || on_top_or_bottom);
+                assert!(pt.x > min_x - EPSILON && pt.x < max_x + EPSILON);
+                assert!(pt.y > 1.5 - EPSILON && pt.y < 8.5 + EPSILON);
+            }
+        }
+
+        // and: the top and bottom of the neck are reported as having disappeared
+        let mut disappeared = offset
+            .disappeared
+            .iter()
+            .map(|feature| (feature.x, feature.y))
+            .collect::<Vec<_>>();
+        disappeared.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+        assert_eq!(disappeared, vec![(11., 4.), (11., 6.)]);
+    }
+
+    #[test]
+    pub fn test_open_contour_outline_has_flat_ends() {
+        // given an L-shaped open contour
+        let contour = open_corner();
+        let options = OffsetOptions {
+            corner_join: CornerJoin::Miter,
+            end_cap: EndCap::Flat,
+            ..Default::default()
+        };
+
+        // when it is outlined
+        let outline = only_contour(contour.outline_contour(1., &options).unwrap());
+
+        // then the outline is closed, 2 wide and squared off at both ends
+        assert!(!outline.is_open());
+        assert!((outline.signed_area().abs() - 40.).abs() < EPSILON);
+        for &(x, y) in &[(0., -1.), (0., 1.), (9., 1.), (9., 10.), (11., 10.), (11., -1.)] {
+            assert!(has_point(&outline, x, y), "missing ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    pub fn test_open_contour_outline_has_round_ends() {
+        // given an L-shaped open contour
+        let contour = open_corner();
+        let options = OffsetOptions {
+            corner_join: CornerJoin::Miter,
+            end_cap: EndCap::Round,
+            ..Default::default()
+        };
+
+        // when it is outlined
+        let outline = only_contour(contour.outline_contour(1., &options).unwrap());
+
+        // then each end gets a half circle around it
+        assert!(!outline.is_open());
+        assert!((outline.signed_area().abs() - (40. + std::f64::consts::PI)).abs() < EPSILON);
+        assert!(outline.contains_point(&Vector3::new(-0.9, 0., 0.)));
+        assert!(outline.contains_point(&Vector3::new(10., 10.9, 0.)));
+    }
+}
@@ -0,0 +1,173 @@
+use crate::contour::Contour;
+use wasm_bindgen::prelude::*;
+
+/// How the gap that opens up at a convex corner is filled in once the segments on either side of it are offset
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CornerJoin {
+    /// Fill the gap with an arc around the original corner. This is the shape the laser beam actually cuts.
+    Round,
+    /// Extend the segments until they meet in a sharp corner, falling back to a straight bevel across the gap if
+    /// the corner would stick out further than the miter limit allows.
+    Miter,
+}
+
+/// What happens to a circle that is smaller than the kerf, like a hole that is too small to cut
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CollapsedCircle {
+    /// Leave the circle out of the drawing
+    Drop,
+    /// Replace the circle with a point at its center, so that the hole can still be found (e.g. to drill it)
+    Point,
+}
+
+/// What the join tolerance is measured against
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToleranceMode {
+    /// The tolerance is a distance, in drawing units
+    Absolute,
+    /// The tolerance is a fraction of the size of the drawing (the length of the diagonal of its extents)
+    RelativeToExtents,
+}
+
+/// How a gap between the ends of two entities is closed up
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GapRepair {
+    /// Move both ends to the middle of the gap. Only lines and polylines can be moved like this, so gaps next to
+    /// arcs get bridged instead.
+    Snap,
+    /// Add a line across the gap
+    Bridge,
+}
+
+/// What happens when the kerf is given in a unit, but the drawing does not say what units it is in ($INSUNITS is
+/// not set)
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UnspecifiedUnits {
+    /// Go by whether $MEASUREMENT says the drawing is metric (millimetres) or imperial (inches), and say so in the
+    /// report
+    Warn,
+    /// Do not offset the drawing
+    Refuse,
+}
+
+/// What happens to a contour whose ends do not meet, like an engraving or score line
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OpenContour {
+    /// Leave it as it is, so that it can be engraved or scored, and list it in the report
+    Leave,
+    /// Do not offset the drawing, and say where the open contours are
+    Refuse,
+    /// Replace it with a closed outline that is offset by the kerf on both sides, like a slot cut along it
+    Outline,
+}
+
+/// The shape of the ends of the outline around an open contour
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EndCap {
+    /// A half circle around the end of the contour. This is the shape the laser beam actually cuts.
+    Round,
+    /// A straight line across the end of the contour
+    Flat,
+}
+
+/// What happens when a contour cannot be offset, or an entity cannot be made into a contour at all
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FailedContour {
+    /// Put the contour or entity into the drawing as it was, and list it in the report along with what went wrong
+    Report,
+    /// Do not offset the drawing, giving the error for the first contour or entity that could not be offset
+    Refuse,
+}
+
+/// Settings for how a drawing is kerf adjusted
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct OffsetOptions {
+    pub corner_join: CornerJoin,
+    /// For mitered corners, how far the tip of the corner can stick out from the original corner, as a multiple
+    /// of the offset amount
+    pub miter_limit: f64,
+    /// Splines and ellipses are replaced with straight lines before offsetting. This is the furthest those lines are
+    /// allowed to stray from the curve, in drawing units, measured at a handful of points along each line.
+    pub curve_tolerance: f64,
+    /// What to do with circles that shrink down to nothing. Arcs that shrink down to nothing are always left out, and
+    /// the entities on either side of them are joined up instead.
+    pub collapsed_circle: CollapsedCircle,
+    /// How far apart the ends of two entities can be while still being joined up into the same contour. Some
+    /// programs export drawings with small gaps between entities that are meant to meet.
+    pub join_tolerance: f64,
+    pub join_tolerance_mode: ToleranceMode,
+    /// Gaps that are too big to be joined up, but no bigger than this, are repaired before contours are made.
+    /// Measured the same way as `join_tolerance`. Gaps are not repaired if this is 0.
+    pub heal_distance: f64,
+    pub gap_repair: GapRepair,
+    pub unspecified_units: UnspecifiedUnits,
+    pub open_contour: OpenContour,
+    /// For open contours that are outlined, the shape of the ends of the outline
+    pub end_cap: EndCap,
+    pub failed_contour: FailedContour,
+}
+
+#[wasm_bindgen]
+impl OffsetOptions {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl Default for OffsetOptions {
+    fn default() -> Self {
+        Self {
+            corner_join: CornerJoin::Round,
+            miter_limit: 4.,
+            curve_tolerance: 0.001,
+            collapsed_circle: CollapsedCircle::Drop,
+            join_tolerance: 1e-6,
+            join_tolerance_mode: ToleranceMode::Absolute,
+            heal_distance: 0.,
+            gap_repair: GapRepair::Bridge,
+            unspecified_units: UnspecifiedUnits::Warn,
+            open_contour: OpenContour::Leave,
+            end_cap: EndCap::Round,
+            failed_contour: FailedContour::Report,
+        }
+    }
+}
+
+impl OffsetOptions {
+    /// Converts a distance that is measured according to `join_tolerance_mode` into drawing units, for a drawing
+    /// whose extents have a diagonal that is `extents_size` long
+    fn absolute_distance(&self, distance: f64, extents_size: f64) -> f64 {
+        match self.join_tolerance_mode {
+            ToleranceMode::Absolute => distance,
+            ToleranceMode::RelativeToExtents => distance * extents_size,
+        }
+    }
+
+    /// The join tolerance in drawing units, for a drawing whose extents have a diagonal that is `extents_size` long
+    pub fn absolute_join_tolerance(&self, extents_size: f64) -> f64 {
+        // Ends that are meant to meet are never closer than rounding error allows
+        self.absolute_distance(self.join_tolerance, extents_size)
+            .max(Contour::EPSILON)
+    }
+
+    /// The same options, with the join tolerance and heal distance worked out in drawing units for a drawing of the
+    /// given size
+    pub fn for_extents(&self, extents_size: f64) -> Self {
+        Self {
+            join_tolerance: self.absolute_join_tolerance(extents_size),
+            heal_distance: self.absolute_distance(self.heal_distance, extents_size),
+            join_tolerance_mode: ToleranceMode::Absolute,
+            ..self.clone()
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use crate::contour::{Contour, VectorWrapper};
+use crate::segment::Segment;
+use dxf::entities::*;
+use dxf::{LwPolylineVertex, Point};
+use nalgebra::Vector3;
+
+/// A corner of a polyline, along with the bulge of the segment that goes from it to the next corner
+struct BulgedVertex {
+    location: Vector3<f64>,
+    bulge: f64,
+}
+
+fn lwpolyline_vertices(poly: &LwPolyline) -> Vec<BulgedVertex> {
+    poly.vertices
+        .iter()
+        .map(|v| BulgedVertex {
+            location: Vector3::new(v.x, v.y, 0.),
+            bulge: v.bulge,
+        })
+        .collect()
+}
+
+fn polyline_vertices(poly: &Polyline) -> Vec<BulgedVertex> {
+    poly.vertices
+        .iter()
+        // Spline frame control points are not on the polyline, they only define the shape of a spline-fit polyline
+        .filter(|v| !v.get_is_spline_frame_control_point())
+        .map(|v| BulgedVertex {
+            location: Vector3::new(v.location.x, v.location.y, 0.),
+            bulge: v.bulge,
+        })
+        .collect()
+}
+
+/// Whether an old-style polyline is a flat polyline, as opposed to a 3D polyline or a mesh
+pub fn is_2d_polyline(poly: &Polyline) -> bool {
+    !poly.get_is_3d_polyline() && !poly.get_is_3d_polygon_mesh() && !poly.get_is_polyface_mesh()
+}
+
+/// Whether the polyline ends where it starts, either because it is flagged as closed or because its last vertex
+/// is on top of its first one
+pub fn lwpolyline_is_closed(poly: &LwPolyline) -> bool {
+    vertices_are_closed(&lwpolyline_vertices(poly), poly.get_is_closed())
+}
+
+fn vertices_are_closed(vertices: &[BulgedVertex], closed_flag: bool) -> bool {
+    match (vertices.first(), vertices.last()) {
+        (Some(first), Some(last)) => {
+            closed_flag || (first.location - last.location).magnitude() < Contour::EPSILON
+        }
+        _ => false,
+    }
+}
+
+/// Whether the old-style polyline ends where it starts, either because it is flagged as closed or because its
+/// last vertex is on top of its first one
+pub fn polyline_is_closed(poly: &Polyline) -> bool {
+    vertices_are_closed(&polyline_vertices(poly), poly.get_is_closed())
+}
+
+/// First and last point of an open polyline
+pub fn lwpolyline_endpoints(poly: &LwPolyline) -> Option<(Vector3<f64>, Vector3<f64>)> {
+    let vertices = lwpolyline_vertices(poly);
+    Some((vertices.first()?.location, vertices.last()?.location))
+}
+
+/// First and last point of an open old-style polyline
+pub fn polyline_endpoints(poly: &Polyline) -> Option<(Vector3<f64>, Vector3<f64>)> {
+    let vertices = polyline_vertices(poly);
+    Some((vertices.first()?.location, vertices.last()?.location))
+}
+
+/// The segments that a polyline is made of, in the order they appear in the polyline
+pub fn lwpolyline_segments(poly: &LwPolyline) -> Vec<Segment> {
+    vertex_segments(&lwpolyline_vertices(poly), poly.get_is_closed())
+}
+
+/// The segments that an old-style polyline is made of, in the order they appear in the polyline
+pub fn polyline_segments(poly: &Polyline) -> Vec<Segment> {
+    vertex_segments(&polyline_vertices(poly), poly.get_is_closed())
+}
+
+fn vertex_segments(vertices: &[BulgedVertex], closed_flag: bool) -> Vec<Segment> {
+    // A closed polyline has one more segment, that goes from the last vertex back to the first
+    let segment_count = if closed_flag {
+        vertices.len()
+    } else {
+        vertices.len().saturating_sub(1)
+    };
+
+    (0..segment_count)
+        .filter_map(|i| {
+            let (from, to) = (&vertices[i], &vertices[(i + 1) % vertices.len()]);
+            if (to.location - from.location).magnitude() < Contour::EPSILON {
+                // Repeated vertices do not make a segment
+                return None;
+            }
+            Some(Segment::from_bulge(from.location, to.location, from.bulge))
+        })
+        .collect()
+}
+
+/// Breaks a polyline up into the line and arc entities that it is made of, in the order they appear in the
+/// polyline. Each of them keeps the layer, color, etc of the polyline.
+pub fn explode_lwpolyline(poly: &LwPolyline, common: &EntityCommon) -> Vec<Entity> {
+    segments_to_entities(lwpolyline_segments(poly), common)
+}
+
+/// Breaks an old-style polyline up into the line and arc entities that it is made of, in the order they appear
+/// in the polyline. Each of them keeps the layer, color, etc of the polyline.
+pub fn explode_polyline(poly: &Polyline, common: &EntityCommon) -> Vec<Entity> {
+    segments_to_entities(polyline_segments(poly), common)
+}
+
+fn segments_to_entities(segments: Vec<Segment>, common: &EntityCommon) -> Vec<Entity> {
+    segments
+        .into_iter()
+        .map(|segment| segment.into_entity(common.clone()))
+        .collect()
+}
+
+/// Builds a polyline that goes along the segments, keeping the non-geometric properties of `template`. A closed
+/// polyline goes from the end of its last segment back to the start of its first one.
+pub fn lwpolyline_along(template: &LwPolyline, segments: &[Segment], is_closed: bool) -> LwPolyline {
+    let mut vertices = segments
+        .iter()
+        .map(|segment| (segment.start(), segment.bulge()))
+        .collect::<Vec<_>>();
+    if let (false, Some(last)) = (is_closed, segments.last()) {
+        vertices.push((last.end(), 0.));
+    }
+
+    let mut poly = LwPolyline {
+        vertices: vertices
+            .into_iter()
+            .map(|(location, bulge)| {
+                let Point { x, y, .. } = VectorWrapper(location).into();
+                LwPolylineVertex {
+                    x,
+                    y,
+                    bulge,
+                    ..Default::default()
+                }
+            })
+            .collect(),
+        ..template.clone()
+    };
+    poly.set_is_closed(is_closed);
+    poly
+}
+
+#[cfg(test)]
+mod polyline_test {
+    use crate::contour::open_ends_of_entity;
+    use crate::polyline::{explode_lwpolyline, lwpolyline_along, lwpolyline_segments};
+    use crate::segment::Segment;
+    use dxf::entities::*;
+    use dxf::LwPolylineVertex;
+    use nalgebra::Vector3;
+
+    const EPSILON: f64 = 1e-6;
+
+    fn vertex(x: f64, y: f64, bulge: f64) -> LwPolylineVertex {
+        LwPolylineVertex {
+            x,
+            y,
+            bulge,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn test_explode_closed_lwpolyline_with_bulges() {
+        // given: a closed slot shape, with a clockwise and a counter-clockwise bulge
+        let mut poly = LwPolyline {
+            vertices: vec![
+                vertex(0., 0., 0.),
+                vertex(10., 0., 1.),
+                vertex(10., 2., 0.),
+                vertex(0., 2., -1.),
+            ],
+            ..Default::default()
+        };
+        poly.set_is_closed(true);
+
+        // when: we break it up
+        let entities = explode_lwpolyline(&poly, &Default::default());
+
+        // then: it is made of 2 lines and 2 semicircles that connect to each other
+        assert_eq!(entities.len(), 4);
+        let arcs = entities
+            .iter()
+            .filter_map(|e| match &e.specific {
+                EntityType::Arc(arc) => Some(arc.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(arcs.len(), 2);
+
+        // the counter-clockwise bulge goes around the right side of the slot, and the clockwise one goes inside of it
+        assert!((arcs[0].center.x - 10.).abs() < EPSILON && (arcs[0].radius - 1.).abs() < EPSILON);
+        assert!((arcs[0].start_angle - 270.).abs() < EPSILON);
+        assert!((arcs[0].end_angle - 90.).abs() < EPSILON);
+        assert!((arcs[1].start_angle - 270.).abs() < EPSILON);
+        assert!((arcs[1].end_angle - 90.).abs() < EPSILON);
+
+        let (start, end) = open_ends_of_entity(&entities[3]).unwrap();
+        assert!((start - Vector3::new(0., 0., 0.)).magnitude() < EPSILON);
+        assert!((end - Vector3::new(0., 2., 0.)).magnitude() < EPSILON);
+    }
+
+    #[test]
+    pub fn test_reversed_segments_make_a_reversed_lwpolyline() {
+        // given: an open polyline with a line and then a counter-clockwise arc
+        let poly = LwPolyline {
+            vertices: vec![vertex(0., 0., 0.), vertex(10., 0., 1.), vertex(10., 2., 0.)],
+            ..Default::default()
+        };
+
+        // when: we walk its segments the other way, and make a polyline out of them
+        let reversed = lwpolyline_segments(&poly)
+            .into_iter()
+            .rev()
+            .map(Segment::reversed)
+            .collect::<Vec<_>>();
+        let reversed = lwpolyline_along(&poly, &reversed, false);
+
+        // then: it starts with a clockwise arc, and then goes back along the line
+        let expected = [(10., 2., -1.), (10., 0., 0.), (0., 0., 0.)];
+        assert_eq!(reversed.vertices.len(), expected.len());
+        for (v, (x, y, bulge)) in reversed.vertices.iter().zip(expected.iter()) {
+            assert!((v.x - x).abs() < EPSILON && (v.y - y).abs() < EPSILON);
+            assert!((v.bulge - bulge).abs() < EPSILON);
+        }
+    }
+}
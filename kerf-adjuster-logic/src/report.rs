@@ -0,0 +1,417 @@
+use crate::kerf::KerfUnit;
+use crate::options::GapRepair;
+use crate::errors::{entity_type_name, KerfAdjustmentError};
+use dxf::entities::{Entity, EntityCommon};
+use nalgebra::Vector3;
+use wasm_bindgen::prelude::*;
+
+/// A part of a contour that is too small to survive kerf adjustment, like a slot that is narrower than the kerf
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct DisappearedFeature {
+    /// Handle of the entity that the feature was part of
+    pub handle: u32,
+    layer: String,
+    /// A point on the feature, to help find it in the drawing
+    pub x: f64,
+    pub y: f64,
+}
+
+impl DisappearedFeature {
+    pub(crate) fn new(common: &EntityCommon, location: &Vector3<f64>) -> Self {
+        Self {
+            handle: common.handle,
+            layer: common.layer.clone(),
+            x: location.x,
+            y: location.y,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl DisappearedFeature {
+    /// Layer of the entity that the feature was part of
+    #[wasm_bindgen(getter)]
+    pub fn layer(&self) -> String {
+        self.layer.clone()
+    }
+}
+
+/// A gap between the ends of two entities that was closed up so that they could be joined into a contour
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct HealedGap {
+    /// The middle of the gap
+    pub x: f64,
+    pub y: f64,
+    /// How far apart the ends were, in drawing units
+    pub size: f64,
+    /// How the gap was closed up
+    pub repair: GapRepair,
+}
+
+impl HealedGap {
+    pub(crate) fn new(a: &Vector3<f64>, b: &Vector3<f64>, repair: GapRepair) -> Self {
+        let middle = (a + b) / 2.;
+        Self {
+            x: middle.x,
+            y: middle.y,
+            size: (a - b).magnitude(),
+            repair,
+        }
+    }
+}
+
+/// A point where more than two entities meet, which makes it unclear how they should be joined into contours
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct Junction {
+    pub x: f64,
+    pub y: f64,
+    /// How many entity ends meet there
+    pub ends: usize,
+}
+
+impl Junction {
+    pub(crate) fn new(location: &Vector3<f64>, ends: usize) -> Self {
+        Self {
+            x: location.x,
+            y: location.y,
+            ends,
+        }
+    }
+}
+
+/// An entity that was put into the offset drawing as it was, either because it is not cut (like text or a dimension)
+/// or because it is part of a contour that could not be offset
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct PassedThroughEntity {
+    pub handle: u32,
+    layer: String,
+    entity_type: String,
+}
+
+impl PassedThroughEntity {
+    pub(crate) fn new(e: &Entity) -> Self {
+        Self {
+            handle: e.common.handle,
+            layer: e.common.layer.clone(),
+            entity_type: entity_type_name(&e.specific),
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl PassedThroughEntity {
+    #[wasm_bindgen(getter)]
+    pub fn layer(&self) -> String {
+        self.layer.clone()
+    }
+
+    /// Name of the kind of entity (e.g. `Text`)
+    #[wasm_bindgen(getter)]
+    pub fn entity_type(&self) -> String {
+        self.entity_type.clone()
+    }
+}
+
+/// A contour whose ends do not meet, like an engraving or score line
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct OpenPath {
+    /// Handle of the entity that the contour starts with
+    pub handle: u32,
+    layer: String,
+    /// Where the contour starts
+    pub start_x: f64,
+    pub start_y: f64,
+    /// Where the contour ends
+    pub end_x: f64,
+    pub end_y: f64,
+}
+
+impl OpenPath {
+    pub(crate) fn new(common: &EntityCommon, start: &Vector3<f64>, end: &Vector3<f64>) -> Self {
+        Self {
+            handle: common.handle,
+            layer: common.layer.clone(),
+            start_x: start.x,
+            start_y: start.y,
+            end_x: end.x,
+            end_y: end.y,
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl OpenPath {
+    /// Layer of the entity that the contour starts with
+    #[wasm_bindgen(getter)]
+    pub fn layer(&self) -> String {
+        self.layer.clone()
+    }
+}
+
+impl std::fmt::Display for OpenPath {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(
+            fmt,
+            "from ({}, {}) to ({}, {}) (entity handle: {:X}, layer: {})",
+            self.start_x, self.start_y, self.end_x, self.end_y, self.handle, self.layer
+        )
+    }
+}
+
+/// A contour that could not be offset, and was put into the offset drawing as it was
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct ContourError {
+    entities: Vec<PassedThroughEntity>,
+    kind: String,
+    message: String,
+}
+
+impl ContourError {
+    pub(crate) fn new(entities: &[Entity], error: &KerfAdjustmentError) -> Self {
+        Self {
+            entities: entities.iter().map(PassedThroughEntity::new).collect(),
+            kind: error.kind(),
+            message: error.message(),
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl ContourError {
+    /// The entities that the contour is made of, in the order they first show up in it
+    #[wasm_bindgen(getter)]
+    pub fn entities(&self) -> Vec<PassedThroughEntity> {
+        self.entities.clone()
+    }
+
+    /// Handles of the entities that the contour is made of
+    #[wasm_bindgen(getter)]
+    pub fn handles(&self) -> Vec<u32> {
+        self.entities.iter().map(|e| e.handle).collect()
+    }
+
+    /// Name of the kind of error (e.g. `CannotConnectContourAfterAdjustment`)
+    #[wasm_bindgen(getter)]
+    pub fn kind(&self) -> String {
+        self.kind.clone()
+    }
+
+    /// Description of the error, to show to the user
+    #[wasm_bindgen(getter)]
+    pub fn message(&self) -> String {
+        self.message.clone()
+    }
+}
+
+/// An entity that could not be made into a contour (like one that is not flat, or a block reference whose block is
+/// missing), and was put into the offset drawing as it was
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct EntityError {
+    pub handle: u32,
+    layer: String,
+    kind: String,
+    message: String,
+}
+
+impl EntityError {
+    pub(crate) fn new(e: &Entity, error: &KerfAdjustmentError) -> Self {
+        Self {
+            handle: e.common.handle,
+            layer: e.common.layer.clone(),
+            kind: error.kind(),
+            message: error.message(),
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl EntityError {
+    #[wasm_bindgen(getter)]
+    pub fn layer(&self) -> String {
+        self.layer.clone()
+    }
+
+    /// Name of the kind of error (e.g. `UnsupportedEntity`)
+    #[wasm_bindgen(getter)]
+    pub fn kind(&self) -> String {
+        self.kind.clone()
+    }
+
+    /// Description of the error, to show to the user. For block references, this names the entity in the block that
+    /// caused it.
+    #[wasm_bindgen(getter)]
+    pub fn message(&self) -> String {
+        self.message.clone()
+    }
+}
+
+/// The kinds of problems that can come up while offsetting a contour without stopping it from being offset
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WarningKind {
+    /// A circle was smaller than the kerf, so there was nothing left of it once offset
+    CollapsedCircle,
+    /// An arc had a smaller radius than the kerf, so there was nothing left of it once offset
+    CollapsedArc,
+}
+
+/// A problem that came up while offsetting, along with the entity that it came up with
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct OffsetWarning {
+    pub kind: WarningKind,
+    /// Handle of the entity that the warning is about
+    pub handle: u32,
+    layer: String,
+}
+
+impl OffsetWarning {
+    pub(crate) fn new(kind: WarningKind, common: &EntityCommon) -> Self {
+        Self {
+            kind,
+            handle: common.handle,
+            layer: common.layer.clone(),
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl OffsetWarning {
+    /// Layer of the entity that the warning is about
+    #[wasm_bindgen(getter)]
+    pub fn layer(&self) -> String {
+        self.layer.clone()
+    }
+
+    /// Description of the warning, to show to the user
+    #[wasm_bindgen(getter)]
+    pub fn message(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl std::fmt::Display for OffsetWarning {
+    fn fmt(&self, fmt: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        let problem = match self.kind {
+            WarningKind::CollapsedCircle => "Circle is smaller than the kerf",
+            WarningKind::CollapsedArc => "Arc has a smaller radius than the kerf",
+        };
+        write!(
+            fmt,
+            "{} (entity handle: {:X}, layer: {})",
+            problem, self.handle, self.layer
+        )
+    }
+}
+
+/// Information about what was done to a drawing while kerf adjusting it
+#[wasm_bindgen]
+#[derive(Clone, Debug, Default)]
+pub struct OffsetReport {
+    /// The furthest any approximated spline or ellipse strays from the original curve, in drawing units, out of the
+    /// points along the curve that were measured
+    pub max_curve_deviation: f64,
+    pub(crate) disappeared_features: Vec<DisappearedFeature>,
+    pub(crate) warnings: Vec<OffsetWarning>,
+    pub(crate) healed_gaps: Vec<HealedGap>,
+    pub(crate) junctions: Vec<Junction>,
+    pub(crate) passed_through: Vec<PassedThroughEntity>,
+    pub(crate) assumed_units: Option<KerfUnit>,
+    pub(crate) open_paths: Vec<OpenPath>,
+    pub(crate) contour_errors: Vec<ContourError>,
+    pub(crate) entity_errors: Vec<EntityError>,
+}
+
+#[wasm_bindgen]
+impl OffsetReport {
+    /// Features that were removed from the drawing because they are too small to cut with this kerf
+    #[wasm_bindgen(getter)]
+    pub fn disappeared_features(&self) -> Vec<DisappearedFeature> {
+        self.disappeared_features.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn warnings(&self) -> Vec<OffsetWarning> {
+        self.warnings.clone()
+    }
+
+    /// Gaps between entities that were closed up before the drawing was offset
+    #[wasm_bindgen(getter)]
+    pub fn healed_gaps(&self) -> Vec<HealedGap> {
+        self.healed_gaps.clone()
+    }
+
+    /// Points where more than two entities meet. The contours around them are made from the smallest closed shapes
+    /// that the entities make, which might not be what was meant.
+    #[wasm_bindgen(getter)]
+    pub fn junctions(&self) -> Vec<Junction> {
+        self.junctions.clone()
+    }
+
+    /// Entities that are not cut, and were put into the offset drawing as they were. They come after the entities
+    /// that are cut, in the order they were in.
+    #[wasm_bindgen(getter)]
+    pub fn passed_through(&self) -> Vec<PassedThroughEntity> {
+        self.passed_through.clone()
+    }
+
+    /// The units the drawing was taken to be in because $INSUNITS was not set, if the kerf had to be converted
+    #[wasm_bindgen(getter)]
+    pub fn assumed_units(&self) -> Option<KerfUnit> {
+        self.assumed_units
+    }
+
+    /// Contours whose ends do not meet. Depending on `OffsetOptions::open_contour`, they were either left as they
+    /// were or replaced with an outline.
+    #[wasm_bindgen(getter)]
+    pub fn open_paths(&self) -> Vec<OpenPath> {
+        self.open_paths.clone()
+    }
+
+    /// Contours that could not be offset, and so were not adjusted for the kerf
+    #[wasm_bindgen(getter)]
+    pub fn contour_errors(&self) -> Vec<ContourError> {
+        self.contour_errors.clone()
+    }
+
+    /// Entities that could not be made into contours, and so were not adjusted for the kerf
+    #[wasm_bindgen(getter)]
+    pub fn entity_errors(&self) -> Vec<EntityError> {
+        self.entity_errors.clone()
+    }
+}
+
+/// A kerf adjusted drawing, along with the report of how it was adjusted
+#[wasm_bindgen]
+#[derive(Debug)]
+pub struct OffsetResult {
+    drawing: Vec<u8>,
+    report: OffsetReport,
+}
+
+impl OffsetResult {
+    pub(crate) fn new(drawing: Vec<u8>, report: OffsetReport) -> Self {
+        Self { drawing, report }
+    }
+}
+
+#[wasm_bindgen]
+impl OffsetResult {
+    /// The bytes of the adjusted DXF file
+    #[wasm_bindgen(getter)]
+    pub fn drawing(&self) -> Vec<u8> {
+        self.drawing.clone()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn report(&self) -> OffsetReport {
+        self.report.clone()
+    }
+}
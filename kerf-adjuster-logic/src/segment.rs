@@ -0,0 +1,604 @@
+//! Directed segments, which contours are made of
+//!
+//! Lines and arcs in a DXF file do not say which way a contour walks along them (arcs always go counter-clockwise,
+//! whichever way the contour goes around them). A segment has an explicit start and end, so a contour made of them
+//! can be reversed or offset without having to work out the direction of each piece again. Each segment remembers
+//! the entity it came from, so that the layer, colour, etc of that entity can be kept when it is written back out.
+use crate::contour::{Contour, VectorWrapper};
+use crate::geometry::*;
+use crate::polyline::{is_2d_polyline, lwpolyline_segments, polyline_segments};
+use dxf::entities::*;
+use nalgebra::Vector3;
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+/// Angle (in radians) of the ray used for point-in-contour tests.
+///
+/// Casting the ray along an axis would make it run straight through the corners of axis-aligned drawings, which
+/// makes the crossing count ambiguous. An arbitrary "odd" angle makes that very unlikely.
+const CONTAINMENT_RAY_ANGLE: f64 = 0.3819;
+
+/// The shape of one piece of a contour, in the direction that the contour walks along it
+#[derive(Clone, Debug)]
+pub enum Segment {
+    Line {
+        start: Vector3<f64>,
+        end: Vector3<f64>,
+    },
+    /// Angles are in radians, counter-clockwise from the x axis
+    Arc {
+        center: Vector3<f64>,
+        radius: f64,
+        start_angle: f64,
+        end_angle: f64,
+        /// Whether the contour walks counter-clockwise around the arc
+        ccw: bool,
+    },
+    /// A circle is a whole contour on its own. It is always walked counter-clockwise.
+    Circle { center: Vector3<f64>, radius: f64 },
+}
+
+impl Segment {
+    /// The segments that an entity (in world coordinates) is made of, in the direction that the entity goes. Arcs
+    /// go counter-clockwise, and polylines go from their first vertex to their last.
+    ///
+    /// Returns `None` for entities that are not made of lines and arcs.
+    pub fn of_entity(e: &Entity) -> Option<Vec<Self>> {
+        let segment = match &e.specific {
+            EntityType::Line(line) => {
+                let (VectorWrapper(start), VectorWrapper(end)) =
+                    (line.p1.clone().into(), line.p2.clone().into());
+                Segment::Line { start, end }
+            }
+            EntityType::Arc(arc) => {
+                let VectorWrapper(center) = arc.center.clone().into();
+                Segment::Arc {
+                    center,
+                    radius: arc.radius,
+                    start_angle: arc.start_angle.to_radians(),
+                    end_angle: arc.end_angle.to_radians(),
+                    ccw: true,
+                }
+            }
+            EntityType::Circle(circle) => {
+                let VectorWrapper(center) = circle.center.clone().into();
+                Segment::Circle {
+                    center,
+                    radius: circle.radius,
+                }
+            }
+            EntityType::LwPolyline(poly) => return Some(lwpolyline_segments(poly)),
+            EntityType::Polyline(poly) if is_2d_polyline(poly) => return Some(polyline_segments(poly)),
+            _ => return None,
+        };
+        Some(vec![segment])
+    }
+
+    /// The segment of a polyline that goes from `start` to `end`, which is an arc if it has a bulge
+    pub(crate) fn from_bulge(start: Vector3<f64>, end: Vector3<f64>, bulge: f64) -> Self {
+        if bulge == 0. {
+            return Segment::Line { start, end };
+        }
+        let (center, radius) = bulge_to_arc(&start, &end, bulge);
+        Segment::Arc {
+            center,
+            radius,
+            start_angle: angle_on_circle(&center, &start),
+            end_angle: angle_on_circle(&center, &end),
+            ccw: bulge > 0.,
+        }
+    }
+
+    /// The bulge that the segment would have as part of a polyline, which is 0 for a line
+    pub fn bulge(&self) -> f64 {
+        match self {
+            Segment::Arc { ccw, .. } => arc_to_bulge(self.sweep(), *ccw),
+            _ => 0.,
+        }
+    }
+
+    pub fn start(&self) -> Vector3<f64> {
+        match self {
+            Segment::Line { start, .. } => *start,
+            Segment::Arc {
+                center,
+                radius,
+                start_angle,
+                ..
+            } => point_on_circle(center, *radius, *start_angle),
+            Segment::Circle { center, radius } => point_on_circle(center, *radius, 0.),
+        }
+    }
+
+    pub fn end(&self) -> Vector3<f64> {
+        match self {
+            Segment::Line { end, .. } => *end,
+            Segment::Arc {
+                center,
+                radius,
+                end_angle,
+                ..
+            } => point_on_circle(center, *radius, *end_angle),
+            Segment::Circle { .. } => self.start(),
+        }
+    }
+
+    /// Direction of travel at the given angle around an arc
+    fn arc_tangent(angle: f64, ccw: bool) -> Vector3<f64> {
+        let ccw_tangent = Vector3::new(-angle.sin(), angle.cos(), 0.);
+        if ccw {
+            ccw_tangent
+        } else {
+            -ccw_tangent
+        }
+    }
+
+    /// Direction of travel at the start of the piece
+    pub fn start_tangent(&self) -> Vector3<f64> {
+        match self {
+            Segment::Line { start, end } => (end - start).normalize(),
+            Segment::Arc {
+                start_angle, ccw, ..
+            } => Self::arc_tangent(*start_angle, *ccw),
+            Segment::Circle { .. } => Self::arc_tangent(0., true),
+        }
+    }
+
+    /// Direction of travel at the end of the piece
+    pub fn end_tangent(&self) -> Vector3<f64> {
+        match self {
+            Segment::Line { start, end } => (end - start).normalize(),
+            Segment::Arc { end_angle, ccw, .. } => Self::arc_tangent(*end_angle, *ccw),
+            Segment::Circle { .. } => Self::arc_tangent(0., true),
+        }
+    }
+
+    /// The point halfway along the piece, and the direction of travel there
+    pub fn midpoint(&self) -> (Vector3<f64>, Vector3<f64>) {
+        match self {
+            Segment::Line { start, end } => ((start + end) / 2., (end - start).normalize()),
+            Segment::Arc {
+                center,
+                radius,
+                start_angle,
+                ccw,
+                ..
+            } => {
+                let half_sweep = self.sweep() / 2.;
+                let mid_angle = if *ccw {
+                    start_angle + half_sweep
+                } else {
+                    start_angle - half_sweep
+                };
+                (
+                    point_on_circle(center, *radius, mid_angle),
+                    Self::arc_tangent(mid_angle, *ccw),
+                )
+            }
+            Segment::Circle { .. } => (self.start(), self.start_tangent()),
+        }
+    }
+
+    /// How far around its circle an arc goes, in radians
+    pub fn sweep(&self) -> f64 {
+        match self {
+            Segment::Arc {
+                start_angle,
+                end_angle,
+                ccw,
+                ..
+            } => {
+                let sweep = if *ccw {
+                    end_angle - start_angle
+                } else {
+                    start_angle - end_angle
+                };
+                let sweep = sweep.rem_euclid(2. * PI);
+                // An arc that was trimmed back to (slightly before) its own start has nothing left of it
+                if sweep > 2. * PI - 1e-9 {
+                    0.
+                } else {
+                    sweep
+                }
+            }
+            Segment::Circle { .. } => 2. * PI,
+            Segment::Line { .. } => 0.,
+        }
+    }
+
+    /// Whether moving an arc or circle sideways by `left_distance` would shrink it down to a point, or past it
+    pub(crate) fn collapses_when_offset(&self, left_distance: f64) -> bool {
+        match self {
+            Segment::Arc { radius, ccw, .. } => {
+                let radius = if *ccw {
+                    radius - left_distance
+                } else {
+                    radius + left_distance
+                };
+                radius < Contour::EPSILON
+            }
+            Segment::Circle { radius, .. } => radius - left_distance < Contour::EPSILON,
+            Segment::Line { .. } => false,
+        }
+    }
+
+    /// Whether the piece has shrunk down to a single point
+    pub(crate) fn is_empty(&self) -> bool {
+        match self {
+            Segment::Line { start, end } => (end - start).magnitude() < Contour::EPSILON,
+            Segment::Arc { radius, .. } => radius * self.sweep() < Contour::EPSILON,
+            Segment::Circle { .. } => false,
+        }
+    }
+
+    pub fn reversed(self) -> Self {
+        match self {
+            Segment::Line { start, end } => Segment::Line {
+                start: end,
+                end: start,
+            },
+            Segment::Arc {
+                center,
+                radius,
+                start_angle,
+                end_angle,
+                ccw,
+            } => Segment::Arc {
+                center,
+                radius,
+                start_angle: end_angle,
+                end_angle: start_angle,
+                ccw: !ccw,
+            },
+            circle => circle,
+        }
+    }
+
+    /// Moves the piece sideways. A positive distance moves it to the left of the direction of travel.
+    ///
+    /// An arc that is moved past its own center ends up on the other side of it. Returns `None` if a circle would
+    /// shrink down to nothing.
+    pub(crate) fn offset(&self, left_distance: f64) -> Option<Self> {
+        match self {
+            Segment::Line { start, end } => {
+                let shift = left_normal(&(end - start)).normalize() * left_distance;
+                Some(Segment::Line {
+                    start: start + shift,
+                    end: end + shift,
+                })
+            }
+            Segment::Arc {
+                center,
+                radius,
+                start_angle,
+                end_angle,
+                ccw,
+            } => {
+                // The center of a counter-clockwise arc is on its left
+                let radius = if *ccw {
+                    radius - left_distance
+                } else {
+                    radius + left_distance
+                };
+                // Past the center, each point of the arc ends up on the opposite side of the center from where it
+                // started. The piece this makes is part of a loop that gets cleaned up afterwards.
+                let flip = if radius < 0. { PI } else { 0. };
+                Some(Segment::Arc {
+                    center: *center,
+                    radius: radius.abs(),
+                    start_angle: start_angle + flip,
+                    end_angle: end_angle + flip,
+                    ccw: *ccw,
+                })
+            }
+            Segment::Circle { center, radius } => {
+                let radius = radius - left_distance;
+                if radius < Contour::EPSILON {
+                    return None;
+                }
+                Some(Segment::Circle {
+                    center: *center,
+                    radius,
+                })
+            }
+        }
+    }
+
+    /// How long the piece is
+    pub fn length(&self) -> f64 {
+        match self {
+            Segment::Line { start, end } => (end - start).magnitude(),
+            Segment::Arc { radius, .. } | Segment::Circle { radius, .. } => {
+                radius * self.sweep()
+            }
+        }
+    }
+
+    /// How far along the piece a point on it (or on its extension) is, measured from the start of the piece.
+    ///
+    /// Points just before the start of an arc are a small negative distance along it, rather than almost all the way
+    /// around its circle.
+    pub(crate) fn distance_along(&self, pt: &Vector3<f64>) -> f64 {
+        match self {
+            Segment::Line { start, end } => (pt - start).dot(&(end - start).normalize()),
+            Segment::Arc {
+                center,
+                radius,
+                start_angle,
+                ccw,
+                ..
+            } => {
+                let angle = angle_on_circle(center, pt);
+                let turned = if *ccw {
+                    angle - start_angle
+                } else {
+                    start_angle - angle
+                }
+                .rem_euclid(2. * PI);
+                if turned > PI + self.sweep() / 2. {
+                    radius * (turned - 2. * PI)
+                } else {
+                    radius * turned
+                }
+            }
+            Segment::Circle { center, radius } => {
+                radius * angle_on_circle(center, pt).rem_euclid(2. * PI)
+            }
+        }
+    }
+
+    /// Distance from a point to the closest point on the piece
+    pub(crate) fn distance_to(&self, pt: &Vector3<f64>) -> f64 {
+        match self {
+            Segment::Line { start, end } => distance_to_segment(pt, start, end),
+            Segment::Arc { center, radius, .. } => {
+                let along = self.distance_along(pt);
+                if along >= 0. && along <= self.length() {
+                    ((pt - center).magnitude() - radius).abs()
+                } else {
+                    (pt - self.start())
+                        .magnitude()
+                        .min((pt - self.end()).magnitude())
+                }
+            }
+            Segment::Circle { center, radius } => ((pt - center).magnitude() - radius).abs(),
+        }
+    }
+
+    /// Moves the start of the piece to a point on the piece (or on its extension)
+    pub(crate) fn with_start(self, pt: Vector3<f64>) -> Self {
+        match self {
+            Segment::Line { end, .. } => Segment::Line { start: pt, end },
+            Segment::Arc {
+                center,
+                radius,
+                end_angle,
+                ccw,
+                ..
+            } => Segment::Arc {
+                center,
+                radius,
+                start_angle: angle_on_circle(&center, &pt),
+                end_angle,
+                ccw,
+            },
+            circle => circle,
+        }
+    }
+
+    /// Moves the end of the piece to a point on the piece (or on its extension)
+    pub(crate) fn with_end(self, pt: Vector3<f64>) -> Self {
+        match self {
+            Segment::Line { start, .. } => Segment::Line { start, end: pt },
+            Segment::Arc {
+                center,
+                radius,
+                start_angle,
+                ccw,
+                ..
+            } => Segment::Arc {
+                center,
+                radius,
+                start_angle,
+                end_angle: angle_on_circle(&center, &pt),
+                ccw,
+            },
+            circle => circle,
+        }
+    }
+
+    /// Points where the (extended) pieces cross each other. Lines are treated as infinitely long, and arcs as full circles.
+    pub(crate) fn intersections(&self, other: &Self) -> Vec<Vector3<f64>> {
+        match (self, other) {
+            (Segment::Line { start: p, end: p2 }, Segment::Line { start: q, end: q2 }) => {
+                line_line_intersection(p, &(p2 - p), q, &(q2 - q))
+                    .into_iter()
+                    .collect()
+            }
+            (Segment::Line { start, end }, Segment::Arc { center, radius, .. })
+            | (Segment::Arc { center, radius, .. }, Segment::Line { start, end }) => {
+                line_circle_intersections(start, &(end - start), center, *radius)
+            }
+            (
+                Segment::Arc {
+                    center: center_a,
+                    radius: radius_a,
+                    ..
+                },
+                Segment::Arc {
+                    center: center_b,
+                    radius: radius_b,
+                    ..
+                },
+            ) => circle_circle_intersections(center_a, *radius_a, center_b, *radius_b),
+            _ => vec![],
+        }
+    }
+
+    /// How much the segment adds to the signed area of a closed contour that it is part of, which is the area between
+    /// the segment and the origin. It is positive where the segment goes counter-clockwise around the origin.
+    pub fn signed_area(&self) -> f64 {
+        match self {
+            Segment::Line { start, end } => cross_z(start, end) / 2.,
+            Segment::Arc { radius, ccw, .. } => {
+                // The triangle out to the chord, plus the part of the circle between the chord and the arc
+                let sweep = self.sweep();
+                let bulge_area = radius * radius * (sweep - sweep.sin()) / 2.;
+                let (start, end) = (self.start(), self.end());
+                let chord_area = cross_z(&start, &end) / 2.;
+                if *ccw {
+                    chord_area + bulge_area
+                } else {
+                    chord_area - bulge_area
+                }
+            }
+            Segment::Circle { radius, .. } => PI * radius * radius,
+        }
+    }
+
+    /// Whether an arc or circle goes through the point on its circle at `angle` (in radians)
+    fn covers_angle(&self, angle: f64) -> bool {
+        match self {
+            Segment::Arc {
+                start_angle,
+                end_angle,
+                ccw,
+                ..
+            } => {
+                let from = if *ccw { start_angle } else { end_angle };
+                (angle - from).rem_euclid(2. * PI) <= self.sweep()
+            }
+            _ => true,
+        }
+    }
+
+    /// Points that the bounding box of the segment goes through
+    pub(crate) fn extent_points(&self) -> Vec<Vector3<f64>> {
+        match self {
+            Segment::Line { start, end } => vec![*start, *end],
+            Segment::Arc { center, radius, .. } | Segment::Circle { center, radius } => (0..4)
+                .map(|quarter| f64::from(quarter) * std::f64::consts::FRAC_PI_2)
+                .filter(|angle| self.covers_angle(*angle))
+                .map(|angle| point_on_circle(center, *radius, angle))
+                .chain(vec![self.start(), self.end()])
+                .collect(),
+        }
+    }
+
+    /// Counts how many times a ray cast from `pt` crosses the segment
+    pub(crate) fn ray_crossings(&self, pt: &Vector3<f64>) -> usize {
+        // Rotate the segment about `pt` so that the ray becomes the positive x axis
+        let rotation = nalgebra::Rotation3::from_axis_angle(&Vector3::z_axis(), -CONTAINMENT_RAY_ANGLE);
+        let to_ray_frame = |p: Vector3<f64>| rotation * (p - pt);
+
+        match self {
+            Segment::Line { start, end } => {
+                let (a, b) = (to_ray_frame(*start), to_ray_frame(*end));
+                if (a.y > 0.) != (b.y > 0.) {
+                    let x_crossing = a.x + (0. - a.y) / (b.y - a.y) * (b.x - a.x);
+                    if x_crossing > 0. {
+                        return 1;
+                    }
+                }
+                0
+            }
+            Segment::Arc { center, radius, .. } | Segment::Circle { center, radius } => {
+                let center = to_ray_frame(*center);
+                if center.y.abs() >= *radius {
+                    return 0;
+                }
+                let half_chord = (radius * radius - center.y * center.y).sqrt();
+                [center.x - half_chord, center.x + half_chord]
+                    .iter()
+                    .filter(|&&x| x > 0.)
+                    .filter(|&&x| {
+                        // Only count the points that are on the arc, going by their angle in the original frame
+                        let angle = (-center.y).atan2(x - center.x) + CONTAINMENT_RAY_ANGLE;
+                        self.covers_angle(angle)
+                    })
+                    .count()
+            }
+        }
+    }
+
+    /// Turns the segment into a line, arc or circle with the given layer, colour, etc
+    pub(crate) fn into_entity(self, common: EntityCommon) -> Entity {
+        let specific = match self {
+            Segment::Line { start, end } => EntityType::Line(Line::new(
+                VectorWrapper(start).into(),
+                VectorWrapper(end).into(),
+            )),
+            Segment::Arc {
+                center,
+                radius,
+                start_angle,
+                end_angle,
+                ccw,
+            } => {
+                // DXF arcs always go counter-clockwise
+                let (start_angle, end_angle) = if ccw {
+                    (start_angle, end_angle)
+                } else {
+                    (end_angle, start_angle)
+                };
+                EntityType::Arc(Arc::new(
+                    VectorWrapper(center).into(),
+                    radius,
+                    start_angle.to_degrees().rem_euclid(360.),
+                    end_angle.to_degrees().rem_euclid(360.),
+                ))
+            }
+            Segment::Circle { center, radius } => {
+                EntityType::Circle(Circle::new(VectorWrapper(center).into(), radius))
+            }
+        };
+        Entity { common, specific }
+    }
+}
+
+/// A segment of a contour, along with the entity in the drawing that it came from
+#[derive(Clone)]
+pub struct ContourSegment {
+    pub shape: Segment,
+    /// The entity that the segment is (part of), in world coordinates. All of the segments of a polyline share the
+    /// polyline, and segments made by offsetting share the entity that they were offset from.
+    pub source: Rc<Entity>,
+}
+
+impl ContourSegment {
+    /// Each of the segments that an entity (in world coordinates) is made of, in the direction that it goes
+    pub fn of_entity(e: Entity) -> Vec<Self> {
+        let shapes = Segment::of_entity(&e).unwrap_or_default();
+        let source = Rc::new(e);
+        shapes
+            .into_iter()
+            .map(|shape| ContourSegment {
+                shape,
+                source: source.clone(),
+            })
+            .collect()
+    }
+
+    /// The same segment, walked the other way
+    pub fn reversed(self) -> Self {
+        ContourSegment {
+            shape: self.shape.reversed(),
+            ..self
+        }
+    }
+
+    /// Turns the segment back into a line, arc or circle with the layer, colour, etc of the entity it came from. If
+    /// it came from the same kind of entity, the thickness of that entity is kept too.
+    pub fn to_entity(&self) -> Entity {
+        let mut e = self.shape.clone().into_entity(self.source.common.clone());
+        match (&mut e.specific, &self.source.specific) {
+            (EntityType::Line(line), EntityType::Line(source)) => line.thickness = source.thickness,
+            (EntityType::Arc(arc), EntityType::Arc(source)) => arc.thickness = source.thickness,
+            (EntityType::Circle(circle), EntityType::Circle(source)) => {
+                circle.thickness = source.thickness
+            }
+            _ => (),
+        }
+        e
+    }
+}
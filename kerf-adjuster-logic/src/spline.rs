@@ -0,0 +1,189 @@
+//! Evaluation of B-spline curves
+use crate::contour::{Contour, VectorWrapper};
+use crate::curve::{approximate_curve, CurveApproximation};
+use dxf::entities::*;
+use nalgebra::Vector3;
+use std::convert::TryFrom;
+
+/// The parts of a DXF spline that are needed to evaluate it
+struct BSpline {
+    degree: usize,
+    knots: Vec<f64>,
+    control_points: Vec<Vector3<f64>>,
+}
+
+impl BSpline {
+    /// Returns `None` if the spline does not have a valid set of knots and control points
+    ///
+    /// The DXF crate only keeps a single weight for the whole spline, and a rational spline with equal weights is the
+    /// same curve as a non-rational one, so weights are not taken into account.
+    fn from_spline(spline: &Spline) -> Option<Self> {
+        let degree = usize::try_from(spline.degree_of_curve).ok()?;
+        let control_points = spline
+            .control_points
+            .iter()
+            .map(|pt| VectorWrapper::from(pt.clone()).0)
+            .collect::<Vec<_>>();
+
+        let valid = degree >= 1
+            && control_points.len() > degree
+            && spline.knot_values.len() == control_points.len() + degree + 1
+            && spline.knot_values.windows(2).all(|w| w[0] <= w[1]);
+        if !valid {
+            return None;
+        }
+
+        Some(Self {
+            degree,
+            knots: spline.knot_values.clone(),
+            control_points,
+        })
+    }
+
+    /// The range of parameter values that the curve is defined over
+    fn domain(&self) -> (f64, f64) {
+        (
+            self.knots[self.degree],
+            self.knots[self.control_points.len()],
+        )
+    }
+
+    /// The boundaries between the polynomial pieces of the curve, from the start of the curve to its end
+    fn span_boundaries(&self) -> Vec<f64> {
+        let (start, end) = self.domain();
+        let mut boundaries = self.knots[self.degree..=self.control_points.len()].to_vec();
+        boundaries.dedup();
+        debug_assert!(boundaries.first() == Some(&start) && boundaries.last() == Some(&end));
+        boundaries
+    }
+
+    /// Point on the curve at the parameter `t`, using de Boor's algorithm
+    fn evaluate(&self, t: f64) -> Vector3<f64> {
+        let p = self.degree;
+        let (_, end) = self.domain();
+
+        // Index of the knot span that contains `t`. The very end of the curve belongs to the last non-empty span.
+        let span = if t >= end {
+            (p..self.control_points.len())
+                .rev()
+                .find(|&i| self.knots[i] < self.knots[i + 1])
+                .unwrap_or(p)
+        } else {
+            (p..self.control_points.len())
+                .rev()
+                .find(|&i| self.knots[i] <= t)
+                .unwrap_or(p)
+        };
+
+        let mut points = self.control_points[span - p..=span].to_vec();
+        for r in 1..=p {
+            for j in (r..=p).rev() {
+                let left = self.knots[j + span - p];
+                let right = self.knots[j + 1 + span - r];
+                let alpha = if right > left {
+                    (t - left) / (right - left)
+                } else {
+                    0.
+                };
+                points[j] = points[j - 1] * (1. - alpha) + points[j] * alpha;
+            }
+        }
+        points[p]
+    }
+}
+
+/// Approximates a spline with straight lines that stay within `tolerance` of the curve
+///
+/// Returns `None` if the spline does not describe a curve. Splines that only have fit points are not supported, since
+/// the curve through them depends on how the CAD program that drew them interpolates between them.
+pub fn approximate_spline(spline: &Spline, tolerance: f64) -> Option<CurveApproximation> {
+    let curve = BSpline::from_spline(spline)?;
+    Some(approximate_curve(
+        &|t| curve.evaluate(t),
+        &curve.span_boundaries(),
+        tolerance,
+    ))
+}
+
+/// Whether the spline describes a curve that can be approximated. Splines that only have fit points do not.
+pub fn spline_has_curve(spline: &Spline) -> bool {
+    BSpline::from_spline(spline).is_some()
+}
+
+/// First and last point of a spline, or `None` if it ends where it starts (or does not describe a curve)
+pub fn spline_endpoints(spline: &Spline) -> Option<(Vector3<f64>, Vector3<f64>)> {
+    let curve = BSpline::from_spline(spline)?;
+    let (start, end) = curve.domain();
+    let points = (curve.evaluate(start), curve.evaluate(end));
+
+    if (points.0 - points.1).magnitude() < Contour::EPSILON {
+        None
+    } else {
+        Some(points)
+    }
+}
+
+#[cfg(test)]
+mod spline_test {
+    use crate::spline::approximate_spline;
+    use dxf::entities::*;
+    use dxf::Point;
+    use nalgebra::Vector3;
+
+    const EPSILON: f64 = 1e-9;
+
+    /// A quadratic spline with a single span, i.e a quadratic bezier curve
+    fn parabola() -> Spline {
+        Spline {
+            degree_of_curve: 2,
+            knot_values: vec![0., 0., 0., 1., 1., 1.],
+            control_points: vec![
+                Point::new(0., 0., 0.),
+                Point::new(5., 10., 0.),
+                Point::new(10., 0., 0.),
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn test_approximation_stays_within_tolerance() {
+        // given: a parabola that peaks at (5, 5)
+        let spline = parabola();
+
+        // when: we approximate it with a coarse and a fine tolerance
+        let coarse = approximate_spline(&spline, 0.1).unwrap();
+        let fine = approximate_spline(&spline, 0.001).unwrap();
+
+        // then: both go from one end of the curve to the other, staying on the curve at their corners
+        for approximation in [&coarse, &fine].iter() {
+            assert!(approximation.max_deviation > 0.);
+            let first = approximation.points.first().unwrap();
+            let last = approximation.points.last().unwrap();
+            assert!((first - Vector3::new(0., 0., 0.)).magnitude() < EPSILON);
+            assert!((last - Vector3::new(10., 0., 0.)).magnitude() < EPSILON);
+            for pt in approximation.points.iter() {
+                // the curve is y = x * (10 - x) / 5
+                assert!((pt.y - pt.x * (10. - pt.x) / 5.).abs() < EPSILON);
+            }
+        }
+
+        // and: a finer tolerance uses more lines to get closer to the curve
+        assert!(coarse.max_deviation <= 0.1);
+        assert!(fine.max_deviation <= 0.001);
+        assert!(fine.points.len() > coarse.points.len());
+    }
+
+    #[test]
+    pub fn test_invalid_spline_is_not_approximated() {
+        // given: a spline that is missing a knot
+        let mut spline = parabola();
+        spline.knot_values.pop();
+
+        // when: we approximate it
+        let approximation = approximate_spline(&spline, 0.1);
+
+        // then: there is no curve to follow
+        assert!(approximation.is_none());
+    }
+}
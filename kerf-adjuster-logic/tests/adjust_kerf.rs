@@ -1,7 +1,22 @@
+mod fixtures;
+
+use fixtures::tiling::{example_contours, tiled_example};
+use fixtures::*;
 use kerfadjusterlogic::contour::ContourVecToDxf;
-use kerfadjusterlogic::contour::find_endpoints_of_entity;
+use kerfadjusterlogic::contour::open_ends_of_entity;
 use kerfadjusterlogic::contour::Contour;
-use dxf::Drawing;
+use kerfadjusterlogic::kerf::{KerfMap, KerfUnit};
+use kerfadjusterlogic::{
+    collapse_contours, offset_drawing, offset_drawing_with_options, offset_drawing_with_unit,
+};
+use kerfadjusterlogic::options::{
+    CollapsedCircle, EndCap, OffsetOptions, OpenContour, ToleranceMode, UnspecifiedUnits,
+};
+use kerfadjusterlogic::report::WarningKind;
+use dxf::entities::*;
+use dxf::enums::{AcadVersion, DrawingUnits, Units};
+use dxf::tables::Layer;
+use dxf::{Block, Color, Drawing, Point, Vector};
 use std::collections::HashMap;
 
 
@@ -30,10 +45,12 @@ fn main() {
         .enumerate()
         .partition::<HashMap<usize, _>, _>(|(_, c)| c.is_open());
 
+    let join_tolerance = OffsetOptions::default().join_tolerance;
+
     // Group the contours with each other.end_points.is_some()
     // TODO: handle unclosed contours
     let mut i = 0;
-    while contours.len() > 0 {
+    while !contours.is_empty() {
         println!("#####\nIter {}:", i);
         i += 1;
         contours.iter().for_each(|(i, contour)| {
@@ -49,56 +66,64 @@ fn main() {
 
             print_endpoints(contour)
         });
+        let mut combined_any = false;
         'each_a: for (i, current_contour) in contours.iter() {
-            loop {
-                for (j, other_contour) in contours.iter() {
-                    let (i, j) = (*i, *j);
-                    if i != j {
-                        if let Ok(combined) = current_contour
-                            .clone()
-                            .combine_attempt(other_contour.clone())
-                        {
-                            // We will combine other_contour into this_contour
-                            // Remove other_contour from contours map
-                            contours.remove(&j);
-
-                            // Check if the combined result is open or closed, and
-                            // put it in its place accordingly
-                            if combined.end_points.is_some() {
-                                contours.insert(i, combined);
-                            } else {
-                                contours.remove(&i);
-                                finished_contours.insert(i, combined);
-                            }
-
-                            // Restart the contour merging process from the beginning
-                            // helps with ensuring consistent behavior
-                            break 'each_a;
+            for (j, other_contour) in contours.iter() {
+                let (i, j) = (*i, *j);
+                if i != j {
+                    if let Ok(combined) = current_contour
+                        .clone()
+                        .combine_attempt(other_contour.clone(), join_tolerance)
+                    {
+                        // We will combine other_contour into this_contour
+                        // Remove other_contour from contours map
+                        contours.remove(&j);
+
+                        // Check if the combined result is open or closed, and
+                        // put it in its place accordingly
+                        if combined.end_points.is_some() {
+                            contours.insert(i, combined);
+                        } else {
+                            contours.remove(&i);
+                            finished_contours.insert(i, combined);
                         }
+
+                        // Restart the contour merging process from the beginning
+                        // helps with ensuring consistent behavior
+                        combined_any = true;
+                        break 'each_a;
                     }
                 }
             }
         }
+
+        // The contours that are left cannot be joined up with anything
+        if !combined_any {
+            break;
+        }
     }
 
     println!("there are {} closed contours", finished_contours.len());
-    for (_, contour) in finished_contours.iter() {
+    for contour in finished_contours.values() {
         print_endpoints(contour);
         println!("\n#######\n");
     }
 
     // test offsetting contours
     let new_drawing = finished_contours
-        .into_iter()
-        .map(|(_, c)| c.offset_contour(0.3).unwrap_or(c))
+        .into_values()
+        .flat_map(|c| match c.offset_contour(0.3, &OffsetOptions::default()) {
+            Ok(offset) => offset.contours,
+            Err(_) => vec![c],
+        })
         .collect::<Vec<_>>()
         .to_dxf();
-    new_drawing.save_file("./tester.dxf");
+    new_drawing.save_file("./tester.dxf").unwrap();
 }
 
 fn print_endpoints(contour: &Contour) {
-    for entity in contour.entities.iter() {
-        match find_endpoints_of_entity(entity) {
+    for entity in contour.to_entities().iter() {
+        match open_ends_of_entity(entity) {
             Some((start, end)) => {
                 let entity_specific_debug = format!("{:?}", entity.specific);
                 let entity_name = entity_specific_debug
@@ -114,5 +139,1384 @@ fn print_endpoints(contour: &Contour) {
         }
         print!(" -> ");
     }
-    print!("\n");
+    println!();
+}
+
+#[test]
+fn test_holes_shrink_and_parts_grow() {
+    // given: a round part with a round hole in it, and a round island inside of the hole
+    let drawing = drawing_of(vec![circle(0., 0., 10.), circle(0., 0., 3.), circle(0., 0., 1.)]);
+    let drawing_bytes = save_to_bytes(&drawing);
+
+    // when: we offset the drawing
+    let offset_bytes = offset_drawing(&drawing_bytes, 0.5).unwrap();
+
+    // then: the part and the island grow, and the hole shrinks
+    assert_eq!(circle_radii(&load(&offset_bytes)), vec![1.5, 2.5, 10.5]);
+}
+
+#[test]
+fn test_polyline_mixed_with_lines() {
+    // given: a square part whose right side bulges out in a semicircle, made of an old-style polyline and two lines,
+    // with a round hole in it
+    let vertex = |x, y, bulge| Vertex {
+        location: Point::new(x, y, 0.),
+        bulge,
+        ..Default::default()
+    };
+    let drawing = drawing_of(vec![
+        Entity::new(EntityType::Polyline(Polyline {
+            vertices: vec![
+                vertex(10., 0., 1.),
+                vertex(10., 10., 0.),
+                vertex(0., 10., 0.),
+            ],
+            ..Default::default()
+        })),
+        line(0., 0., 0., 10.),
+        line(10., 0., 0., 0.),
+        circle(5., 5., 2.),
+    ]);
+
+    // when: we offset the drawing
+    let (offset, _) = offset_by(&drawing, 0.5, &OffsetOptions::default());
+
+    // then: the outline grows and the hole shrinks
+    let mut arc_radii = offset
+        .entities
+        .iter()
+        .filter_map(|e| match &e.specific {
+            EntityType::Arc(arc) if (arc.radius - 0.5).abs() > 1e-6 => Some(arc.radius),
+            EntityType::Circle(circle) => Some(circle.radius),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    arc_radii.sort_by(f64::total_cmp);
+    assert_eq!(arc_radii, vec![1.5, 5.5]);
+    let left_side = offset.entities.iter().any(|e| match &e.specific {
+        EntityType::Line(line) => line.p1.x == -0.5 && line.p2.x == -0.5,
+        _ => false,
+    });
+    assert!(left_side);
+}
+
+/// The polylines in the drawing
+fn lwpolylines(drawing: &Drawing) -> Vec<&LwPolyline> {
+    drawing
+        .entities
+        .iter()
+        .filter_map(|e| match &e.specific {
+            EntityType::LwPolyline(poly) => Some(poly),
+            _ => None,
+        })
+        .collect()
+}
+
+fn assert_extents(actual: (f64, f64, f64, f64), expected: (f64, f64, f64, f64)) {
+    let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+    assert!(
+        close(actual.0, expected.0) && close(actual.1, expected.1) && close(actual.2, expected.2) && close(actual.3, expected.3),
+        "{:?} is not {:?}",
+        actual,
+        expected
+    );
+}
+
+#[test]
+fn test_lwpolyline_rectangle_stays_a_polyline() {
+    // given: a 10x5 rectangular part drawn as one closed polyline, with a rectangular hole drawn as another
+    let drawing = drawing_of(vec![
+        lwpolyline(&[(0., 0., 0.), (10., 0., 0.), (10., 5., 0.), (0., 5., 0.)], true),
+        lwpolyline(&[(2., 1., 0.), (8., 1., 0.), (8., 4., 0.), (2., 4., 0.)], true),
+    ]);
+
+    // when: we offset the drawing
+    let (offset, _) = offset_by(&drawing, 0.5, &OffsetOptions::default());
+
+    // then: the part and the hole are each still a single closed polyline
+    assert_eq!(offset.entities.len(), 2);
+    let mut polys = lwpolylines(&offset);
+    assert_eq!(polys.len(), 2);
+    assert!(polys.iter().all(|poly| poly.get_is_closed()));
+
+    // and: the part grows by the kerf on every side, and the hole shrinks by it
+    polys.sort_by(|a, b| lwpolyline_extents(a).0.total_cmp(&lwpolyline_extents(b).0));
+    assert_extents(lwpolyline_extents(polys[0]), (-0.5, -0.5, 10.5, 5.5));
+    assert_extents(lwpolyline_extents(polys[1]), (2.5, 1.5, 7.5, 3.5));
+}
+
+#[test]
+fn test_lwpolyline_with_bulges_stays_a_polyline() {
+    // given: a slot from x = -2 to x = 12 drawn as one closed polyline, whose rounded ends are bulges
+    let drawing = drawing_of(vec![lwpolyline(
+        &[(0., 0., 0.), (10., 0., 1.), (10., 4., 0.), (0., 4., 1.)],
+        true,
+    )]);
+
+    // when: we offset the drawing
+    let (offset, _) = offset_by(&drawing, 0.5, &OffsetOptions::default());
+
+    // then: the slot is still a single closed polyline, with its ends still rounded
+    assert_eq!(offset.entities.len(), 1);
+    let polys = lwpolylines(&offset);
+    assert_eq!(polys.len(), 1);
+    assert!(polys[0].get_is_closed());
+    assert_eq!(polys[0].vertices.iter().filter(|v| (v.bulge - 1.).abs() < 1e-9).count(), 2);
+
+    // and: it grows by the kerf all the way around
+    assert_extents(lwpolyline_extents(polys[0]), (-2.5, -0.5, 12.5, 4.5));
+}
+
+#[test]
+fn test_spline_is_approximated_and_offset() {
+    // given: a part shaped like a dome, with a spline across the top and a line along the bottom
+    let drawing = drawing_of(vec![
+        Entity::new(EntityType::Spline(Spline {
+            degree_of_curve: 2,
+            knot_values: vec![0., 0., 0., 1., 1., 1.],
+            control_points: vec![
+                Point::new(0., 0., 0.),
+                Point::new(5., 10., 0.),
+                Point::new(10., 0., 0.),
+            ],
+            ..Default::default()
+        })),
+        line(0., 0., 10., 0.),
+    ]);
+
+    // when: we offset the drawing
+    let options = OffsetOptions {
+        curve_tolerance: 0.01,
+        ..Default::default()
+    };
+    let (offset, report) = offset_by(&drawing, 0.5, &options);
+
+    // then: the curve was followed as closely as we asked
+    let deviation = report.max_curve_deviation;
+    assert!(deviation > 0. && deviation <= 0.01);
+
+    // and: the whole dome grew, including its flat bottom
+    let bottom = offset.entities.iter().any(|e| match &e.specific {
+        EntityType::LwPolyline(poly) => poly
+            .vertices
+            .iter()
+            .any(|v| v.x.abs() < 1e-6 && (v.y + 0.5).abs() < 1e-6),
+        _ => false,
+    });
+    assert!(bottom);
+}
+
+#[test]
+fn test_ellipse_is_offset() {
+    // given: an elliptical part that is 20 wide and 10 tall, with an elliptical hole in it that is half the size
+    let ellipse = |half_width: f64| {
+        Entity::new(EntityType::Ellipse(Ellipse {
+            major_axis: Vector::new(half_width, 0., 0.),
+            minor_axis_ratio: 0.5,
+            ..Default::default()
+        }))
+    };
+    let drawing = drawing_of(vec![ellipse(10.), ellipse(5.)]);
+
+    // when: we offset the drawing
+    let (offset, report) = offset_by(&drawing, 0.5, &OffsetOptions::default());
+
+    // then: the part grows and the hole shrinks, both staying close to the true offset
+    let mut right_edges = offset
+        .entities
+        .iter()
+        .filter_map(|e| match &e.specific {
+            EntityType::LwPolyline(poly) => {
+                Some(poly.vertices.iter().map(|v| v.x).fold(0., f64::max))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    right_edges.sort_by(f64::total_cmp);
+    assert_eq!(right_edges.len(), 2);
+    let tolerance = OffsetOptions::default().curve_tolerance;
+    assert!((right_edges[0] - 4.5).abs() <= tolerance);
+    assert!((right_edges[1] - 10.5).abs() <= tolerance);
+    assert!(report.max_curve_deviation <= tolerance);
+}
+
+#[test]
+fn test_mirrored_arcs_are_offset() {
+    // given: a slot whose rounded ends are arcs seen from below, as mirrored parts often are. In world coordinates
+    // the slot goes from x = -11 to x = 1.
+    let mirrored_arc = |center_x, start_angle, end_angle| {
+        Entity::new(EntityType::Arc(Arc {
+            center: Point::new(center_x, 0., 0.),
+            radius: 1.,
+            normal: Vector::new(0., 0., -1.),
+            start_angle,
+            end_angle,
+            ..Default::default()
+        }))
+    };
+    let drawing = drawing_of(vec![
+        mirrored_arc(0., 90., 270.),
+        mirrored_arc(10., 270., 90.),
+        line(0., 1., -10., 1.),
+        line(0., -1., -10., -1.),
+    ]);
+
+    // when: we offset the drawing
+    let (offset, _) = offset_by(&drawing, 0.5, &OffsetOptions::default());
+
+    // then: the ends of the slot grow around their world coordinate centers, and are written facing up
+    let mut arcs = offset
+        .entities
+        .iter()
+        .filter_map(|e| match &e.specific {
+            EntityType::Arc(arc) => Some(arc.clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    arcs.sort_by(|a, b| a.center.x.total_cmp(&b.center.x));
+    assert_eq!(arcs.len(), 2);
+    assert_eq!((arcs[0].center.x, arcs[1].center.x), (-10., 0.));
+    for arc in arcs {
+        assert_eq!(arc.radius, 1.5);
+        assert_eq!(arc.normal, Vector::z_axis());
+    }
+}
+
+#[test]
+fn test_arcs_and_lines_drawn_against_the_contour_are_offset() {
+    // given: a slot from x = -1 to x = 11 whose top side is drawn from left to right, against the way the bottom
+    // side and left end go round, and whose right end is an arc seen from below
+    let right_end = Entity::new(EntityType::Arc(Arc {
+        center: Point::new(-10., 0., 0.),
+        radius: 1.,
+        normal: Vector::new(0., 0., -1.),
+        start_angle: 90.,
+        end_angle: 270.,
+        ..Default::default()
+    }));
+    let drawing = drawing_of(vec![
+        Entity::new(EntityType::Arc(Arc::new(Point::origin(), 1., 90., 270.))),
+        line(0., -1., 10., -1.),
+        right_end,
+        line(0., 1., 10., 1.),
+    ]);
+
+    // when: we offset the drawing
+    let (offset, report) = offset_by(&drawing, 0.5, &OffsetOptions::default());
+
+    // then: the whole slot is joined up and offset, with nothing left out
+    assert!(report.contour_errors().is_empty() && report.open_paths().is_empty());
+    assert_eq!(offset.entities.len(), 4);
+
+    // and: both sides move out by the kerf, and meet the ends of the slot
+    let points = end_points(&offset);
+    assert_eq!(points.len(), 8);
+    for &(x, y) in &[(0., 1.5), (10., 1.5), (0., -1.5), (10., -1.5)] {
+        let meeting = points
+            .iter()
+            .filter(|pt| (pt.x - x).abs() < 1e-9 && (pt.y - y).abs() < 1e-9)
+            .count();
+        assert_eq!(meeting, 2, "({}, {})", x, y);
+    }
+
+    // and: both ends grow around their world coordinate centres, and are written facing up
+    let mut arcs = offset
+        .entities
+        .iter()
+        .filter_map(|e| match &e.specific {
+            EntityType::Arc(arc) => Some(arc.clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    arcs.sort_by(|a, b| a.center.x.total_cmp(&b.center.x));
+    assert_eq!(arcs.len(), 2);
+    assert_eq!((arcs[0].center.x, arcs[1].center.x), (0., 10.));
+    for arc in arcs {
+        assert!((arc.radius - 1.5).abs() < 1e-9);
+        assert_eq!(arc.normal, Vector::z_axis());
+    }
+}
+
+#[test]
+fn test_contours_drawn_clockwise_are_offset_the_same_way() {
+    // given: a 10x10 square part with a 4x4 square hole in the middle, both drawn clockwise
+    let clockwise_square = |min: f64, max: f64| {
+        vec![
+            line(min, min, min, max),
+            line(min, max, max, max),
+            line(max, max, max, min),
+            line(max, min, min, min),
+        ]
+    };
+    let mut entities = clockwise_square(0., 10.);
+    entities.extend(clockwise_square(3., 7.));
+    let drawing = drawing_of(entities);
+
+    // when: we offset the drawing
+    let (offset, _) = offset_by(&drawing, 0.5, &OffsetOptions::default());
+
+    // then: the part grows by the kerf on every side, with rounded corners
+    let points = end_points(&offset);
+    for &(x, y) in &[(-0.5, 0.), (-0.5, 10.), (0., 10.5), (10., 10.5), (10.5, 10.), (10.5, 0.), (10., -0.5), (0., -0.5)] {
+        assert!(has_point(&points, x, y), "missing ({}, {})", x, y);
+    }
+
+    // and: the hole shrinks by the kerf on every side, keeping its sharp corners
+    for &(x, y) in &[(3.5, 3.5), (3.5, 6.5), (6.5, 6.5), (6.5, 3.5)] {
+        assert!(has_point(&points, x, y), "missing ({}, {})", x, y);
+    }
+
+    // and: nothing was offset to the other side
+    let on_part = |x: f64, y: f64| (x.min(y) + 0.5).abs() < 1e-9 || (x.max(y) - 10.5).abs() < 1e-9;
+    let on_hole = |x: f64, y: f64| (x.min(y) - 3.5).abs() < 1e-9 || (x.max(y) - 6.5).abs() < 1e-9;
+    assert!(points.iter().all(|pt| on_part(pt.x, pt.y) || on_hole(pt.x, pt.y)));
+}
+
+#[test]
+fn test_hole_smaller_than_kerf_disappears() {
+    // given: a round part with a hole in it that is narrower than the kerf
+    let mut hole = circle(2., 0., 0.4);
+    hole.common.layer = String::from("holes");
+    let drawing = drawing_of(vec![circle(0., 0., 10.), hole]);
+
+    // when: we offset the drawing
+    let (offset, report) = offset_by(&drawing, 0.5, &OffsetOptions::default());
+
+    // then: only the outside of the part is left
+    assert_eq!(offset.entities.len(), 1);
+
+    // and: the hole is reported as having disappeared
+    let disappeared = report.disappeared_features();
+    assert_eq!(disappeared.len(), 1);
+    assert_eq!(disappeared[0].layer(), "holes");
+    assert!((disappeared[0].x - 2.4).abs() < 1e-6 && disappeared[0].y.abs() < 1e-6);
+}
+
+#[test]
+fn test_collapsed_hole_can_be_marked_with_a_point() {
+    // given: a round part with a hole in it that is narrower than the kerf
+    let mut hole = circle(2., 0., 0.4);
+    hole.common.layer = String::from("holes");
+    let drawing = drawing_of(vec![circle(0., 0., 10.), hole]);
+
+    // when: we offset the drawing, asking for collapsed holes to be marked
+    let options = OffsetOptions {
+        collapsed_circle: CollapsedCircle::Point,
+        ..Default::default()
+    };
+    let (offset, report) = offset_by(&drawing, 0.5, &options);
+
+    // then: the hole is replaced by a point at its center
+    let points = offset
+        .entities
+        .iter()
+        .filter_map(|e| match &e.specific {
+            EntityType::ModelPoint(pt) => Some(pt.location.clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(points, vec![Point::new(2., 0., 0.)]);
+    assert!(report.disappeared_features().is_empty());
+
+    // and: there is a warning that names the hole
+    let warnings = report.warnings();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].kind, WarningKind::CollapsedCircle);
+    assert_eq!(warnings[0].layer(), "holes");
+    assert!(warnings[0].message().contains("layer: holes"));
+}
+
+#[test]
+fn test_collapsed_fillets_are_reported() {
+    // given: a part with a square hole in it, whose corners are rounded off by less than the kerf
+    let fillet = |x: f64, y: f64, start_angle: f64| {
+        let mut e = Entity::new(EntityType::Arc(Arc::new(
+            Point::new(x, y, 0.),
+            0.2,
+            start_angle,
+            start_angle + 90.,
+        )));
+        e.common.layer = String::from("hole");
+        e
+    };
+    let side = |x1: f64, y1: f64, x2: f64, y2: f64| {
+        let mut e = line(x1, y1, x2, y2);
+        e.common.layer = String::from("hole");
+        e
+    };
+    let drawing = load(&save_to_bytes(&drawing_of(vec![
+        circle(5., 5., 20.),
+        side(2.2, 2., 7.8, 2.),
+        fillet(7.8, 2.2, 270.),
+        side(8., 2.2, 8., 7.8),
+        fillet(7.8, 7.8, 0.),
+        side(7.8, 8., 2.2, 8.),
+        fillet(2.2, 7.8, 90.),
+        side(2., 7.8, 2., 2.2),
+        fillet(2.2, 2.2, 180.),
+    ])));
+
+    // when: we offset the drawing
+    let (offset, report) = offset_by(&drawing, 0.5, &OffsetOptions::default());
+
+    // then: each of the fillets is reported as having collapsed
+    let fillet_handles = drawing
+        .entities
+        .iter()
+        .filter(|e| matches!(e.specific, EntityType::Arc(_)))
+        .map(|e| e.common.handle)
+        .collect::<Vec<_>>();
+    let warnings = report.warnings();
+    assert_eq!(warnings.len(), 4);
+    assert!(warnings.iter().all(|w| w.kind == WarningKind::CollapsedArc
+        && w.layer() == "hole"
+        && fillet_handles.contains(&w.handle)));
+
+    // and: the hole is still there, with sharp corners
+    let hole_lines = offset
+        .entities
+        .iter()
+        .filter(|e| matches!(e.specific, EntityType::Line(_)))
+        .count();
+    assert_eq!(hole_lines, 4);
+}
+
+#[test]
+fn test_small_gaps_are_joined_within_tolerance() {
+    // given: a 10x10 square whose sides have gaps of 0.0001 between them
+    let drawing = drawing_of(vec![
+        line(0., 0., 10., 0.),
+        line(10., 0.0001, 10., 10.),
+        line(10., 10.0001, 0., 10.),
+        line(0., 10., 0., 0.0001),
+    ]);
+    let offset_extents = |options: &OffsetOptions| {
+        let points = end_points(&offset_by(&drawing, 0.5, options).0);
+        let min_x = points.iter().map(|pt| pt.x).fold(f64::INFINITY, f64::min);
+        let max_x = points.iter().map(|pt| pt.x).fold(f64::NEG_INFINITY, f64::max);
+        (min_x, max_x)
+    };
+
+    // when: we offset it with the default tolerance, and with tolerances that are bigger than the gaps
+    let default = offset_extents(&OffsetOptions::default());
+    let absolute = offset_extents(&OffsetOptions {
+        join_tolerance: 0.001,
+        ..Default::default()
+    });
+    let relative = offset_extents(&OffsetOptions {
+        join_tolerance: 0.0001,
+        join_tolerance_mode: ToleranceMode::RelativeToExtents,
+        ..Default::default()
+    });
+
+    // then: the default tolerance leaves the square open, so it is not offset
+    assert!(default.0.abs() < 1e-6 && (default.1 - 10.).abs() < 1e-6);
+
+    // and: with a big enough tolerance, the square is joined up and grows
+    for (min_x, max_x) in [absolute, relative] {
+        assert!((min_x + 0.5).abs() < 1e-3, "{}", min_x);
+        assert!((max_x - 10.5).abs() < 1e-3, "{}", max_x);
+    }
+}
+
+#[test]
+fn test_gaps_are_healed_and_reported() {
+    // given: a 10x10 square whose sides have gaps of 0.01 between them
+    let drawing = drawing_of(vec![
+        line(0., 0., 10., 0.),
+        line(10., 0.01, 10., 10.),
+        line(10., 10.01, 0., 10.),
+        line(0., 9.99, 0., 0.01),
+    ]);
+
+    // when: we offset it, healing gaps of up to 0.05
+    let options = OffsetOptions {
+        heal_distance: 0.05,
+        ..Default::default()
+    };
+    let (offset, report) = offset_by(&drawing, 0.5, &options);
+
+    // then: the square is closed up with a line across each gap, and grows
+    let min_x = offset
+        .entities
+        .iter()
+        .filter_map(open_ends_of_entity)
+        .map(|(start, end)| start.x.min(end.x))
+        .fold(f64::INFINITY, f64::min);
+    assert!((min_x + 0.5).abs() < 1e-6, "{}", min_x);
+
+    // and: every gap is reported
+    let gaps = report.healed_gaps();
+    assert_eq!(gaps.len(), 4);
+    assert!(gaps.iter().all(|gap| (gap.size - 0.01).abs() < 1e-9));
+    assert!(gaps
+        .iter()
+        .any(|gap| (gap.x - 10.).abs() < 1e-9 && (gap.y - 0.005).abs() < 1e-9));
+}
+
+#[test]
+fn test_bridges_are_drawn_like_the_contour_they_close() {
+    // given: a red 10x10 square on a layer of its own, with a gap of 0.01 where its last side meets its first
+    let side = |x1: f64, y1: f64, x2: f64, y2: f64| {
+        let mut e = line(x1, y1, x2, y2);
+        e.common.layer = String::from("outline");
+        e.common.color = Color::from_index(1);
+        e
+    };
+    let drawing = drawing_of(vec![
+        side(0., 0.01, 0., 10.),
+        side(0., 10., 10., 10.),
+        side(10., 10., 10., 0.),
+        side(10., 0., 0., 0.),
+    ]);
+
+    // when: we offset red by 0.1 and everything else by 0.5, bridging the gap
+    let mut kerfs = KerfMap::new(0.5);
+    kerfs.set_color(1, 0.1);
+    let options = OffsetOptions {
+        heal_distance: 0.05,
+        ..Default::default()
+    };
+    let (offset, report) = offset_with_kerfs(&drawing, &kerfs, &options);
+
+    // then: the gap is bridged, and the square grows by the kerf for red
+    assert_eq!(report.healed_gaps().len(), 1);
+    let points = end_points(&offset);
+    let min_x = points.iter().map(|pt| pt.x).fold(f64::INFINITY, f64::min);
+    assert!((min_x + 0.1).abs() < 1e-6, "{}", min_x);
+
+    // and: everything that was written out, the bridge included, is red and on the square's layer
+    assert!(offset
+        .entities
+        .iter()
+        .all(|e| e.common.color == Color::from_index(1) && e.common.layer == "outline"));
+}
+
+#[test]
+fn test_invalid_file_is_an_error() {
+    // given: bytes that are not a DXF file
+    let drawing_bytes = b"not a drawing".to_vec();
+
+    // when: we try to offset them
+    let error = offset_drawing(&drawing_bytes, 0.5).unwrap_err();
+
+    // then: the error says the file could not be read, and no entity is blamed
+    assert_eq!(error.kind(), "InvalidDxf");
+    assert!(error.handle().is_none() && error.layer().is_none());
+}
+
+#[test]
+fn test_unsupported_entity_is_an_error() {
+    // given: a drawing with a 3D face in it, which cannot be offset
+    let mut face = Entity::new(EntityType::Face3D(Face3D::default()));
+    face.common.layer = String::from("faces");
+    let drawing = drawing_of(vec![circle(0., 0., 10.), face]);
+
+    // when: we try to offset it, refusing entities that cannot be offset
+    let error = offset_error(&drawing, 0.5, &refusing_failures());
+
+    // then: the error names the 3D face as the problem
+    assert_eq!(error.kind(), "UnsupportedEntity");
+    assert_eq!(error.layer().as_deref(), Some("faces"));
+    assert!(error.handle().is_some());
+}
+
+#[test]
+fn test_text_is_left_as_it_is() {
+    // given: a round part with a label on it
+    let text = Text {
+        location: Point::new(1., 2., 0.),
+        value: String::from("part 1"),
+        ..Default::default()
+    };
+    let drawing = drawing_of(vec![circle(0., 0., 10.), Entity::new(EntityType::Text(text))]);
+
+    // when: we offset the drawing
+    let (offset, _) = offset_by(&drawing, 0.5, &OffsetOptions::default());
+
+    // then: the part grows, and the label is still there, unchanged
+    assert_eq!(offset.entities.len(), 2);
+    assert!(offset.entities.iter().any(|e| match &e.specific {
+        EntityType::Circle(circle) => (circle.radius - 10.5).abs() < 1e-6,
+        _ => false,
+    }));
+    assert!(offset.entities.iter().any(|e| match &e.specific {
+        EntityType::Text(text) => text.value == "part 1" && text.location == Point::new(1., 2., 0.),
+        _ => false,
+    }));
+}
+
+#[test]
+fn test_tilted_arc_is_an_error() {
+    // given: a drawing with an arc that is tilted out of the XY plane
+    let tilted_arc = Entity::new(EntityType::Arc(Arc {
+        radius: 1.,
+        end_angle: 90.,
+        normal: Vector::new(0., 1., 1.),
+        ..Default::default()
+    }));
+    let drawing = drawing_of(vec![tilted_arc]);
+
+    // when: we try to offset it, refusing entities that cannot be offset
+    let error = offset_error(&drawing, 0.5, &refusing_failures());
+
+    // then: the error says that the arc cannot be cut
+    assert_eq!(error.kind(), "ThreeDimensionalEntity");
+    assert!(error.handle().is_some());
+}
+
+#[test]
+fn test_lines_and_splines_out_of_the_xy_plane_are_errors() {
+    // given: a line that climbs out of the XY plane, and a spline whose control points do
+    let climbing_line = Entity::new(EntityType::Line(Line::new(
+        Point::new(0., 0., 0.),
+        Point::new(10., 0., 5.),
+    )));
+    let mut climbing_spline = Spline {
+        degree_of_curve: 2,
+        knot_values: vec![0., 0., 0., 1., 1., 1.],
+        ..Default::default()
+    };
+    for (x, z) in [(0., 0.), (5., 3.), (10., 0.)] {
+        climbing_spline.control_points.push(Point::new(x, 0., z));
+    }
+    let climbing_spline = Entity::new(EntityType::Spline(climbing_spline));
+
+    for entity in [climbing_line, climbing_spline] {
+        let drawing = drawing_of(vec![entity]);
+
+        // when: we try to offset it, refusing entities that cannot be offset
+        let error = offset_error(&drawing, 0.5, &refusing_failures());
+
+        // then: the error says that it cannot be cut
+        assert_eq!(error.kind(), "ThreeDimensionalEntity");
+        assert!(error.handle().is_some());
+    }
+}
+
+#[test]
+fn test_header_and_layers_are_kept() {
+    // given: a millimetre drawing with a square part on a red layer
+    let corners = [(0., 0.), (10., 0.), (10., 10.), (0., 10.)];
+    let square = (0..4)
+        .map(|i| {
+            let ((x1, y1), (x2, y2)) = (corners[i], corners[(i + 1) % 4]);
+            let mut side = line(x1, y1, x2, y2);
+            side.common.layer = String::from("outline");
+            side
+        })
+        .collect();
+    let mut drawing = drawing_of(square);
+    drawing.layers.push(Layer {
+        name: String::from("outline"),
+        color: Color::from_index(1),
+        ..Default::default()
+    });
+    drawing.header.default_drawing_units = Units::Millimeters;
+
+    // when: we offset the drawing
+    let (offset, _) = offset_by(&drawing, 0.5, &OffsetOptions::default());
+
+    // then: the version, units and layer are the same as they were
+    assert_eq!(offset.header.version, AcadVersion::R2000);
+    assert_eq!(offset.header.default_drawing_units, Units::Millimeters);
+    let layer = offset
+        .layers
+        .iter()
+        .find(|layer| layer.name == "outline")
+        .unwrap();
+    assert_eq!(layer.color, Color::from_index(1));
+
+    // and: the offset entities are still on the layer, and each of them has a handle of its own
+    assert_eq!(offset.entities.len(), 8);
+    assert!(offset.entities.iter().all(|e| e.common.layer == "outline"));
+    let mut handles = offset
+        .entities
+        .iter()
+        .map(|e| e.common.handle)
+        .collect::<Vec<_>>();
+    handles.sort_unstable();
+    handles.dedup();
+    assert_eq!(handles.len(), offset.entities.len());
+}
+
+#[test]
+fn test_entities_that_are_not_cut_are_passed_through_in_order() {
+    // given: a round part with a note, a centre mark and a leader in between its outline and its hole, and a fill
+    // after them
+    let note = Entity::new(EntityType::MText(MText {
+        text: String::from("3mm acrylic"),
+        ..Default::default()
+    }));
+    let leader = Entity::new(EntityType::Leader(Leader {
+        vertices: vec![Point::new(12., 12., 0.), Point::new(9., 9., 0.)],
+        ..Default::default()
+    }));
+    let drawing = drawing_of(vec![
+        circle(0., 0., 10.),
+        note,
+        Entity::new(EntityType::ModelPoint(ModelPoint::new(Point::origin()))),
+        circle(0., 0., 2.),
+        leader,
+        Entity::new(EntityType::Solid(Solid::default())),
+    ]);
+
+    // when: we offset the drawing
+    let (offset, report) = offset_by(&drawing, 0.5, &OffsetOptions::default());
+
+    // then: the note, centre mark, leader and fill are still there, after the offset part and in the order they were
+    // in the drawing
+    assert_eq!(offset.entities.len(), 6);
+    assert!(matches!(&offset.entities[0].specific, EntityType::Circle(_)));
+    assert!(matches!(&offset.entities[1].specific, EntityType::Circle(_)));
+    assert!(matches!(&offset.entities[2].specific, EntityType::MText(text) if text.text == "3mm acrylic"));
+    assert!(matches!(&offset.entities[3].specific, EntityType::ModelPoint(point) if point.location == Point::origin()));
+    assert!(matches!(&offset.entities[4].specific, EntityType::Leader(leader) if leader.vertices.len() == 2));
+    assert!(matches!(&offset.entities[5].specific, EntityType::Solid(_)));
+    let kept = offset
+        .entities
+        .iter()
+        .filter(|e| !matches!(e.specific, EntityType::Circle(_)))
+        .collect::<Vec<_>>();
+
+    // and: the report lists them, in the same order
+    let passed_through = report.passed_through();
+    let types = passed_through
+        .iter()
+        .map(|e| e.entity_type())
+        .collect::<Vec<_>>();
+    assert_eq!(types, vec!["MText", "ModelPoint", "Leader", "Solid"]);
+    assert!(passed_through
+        .iter()
+        .zip(kept)
+        .all(|(reported, kept)| reported.handle == kept.common.handle));
+}
+
+#[test]
+fn test_each_layer_has_its_own_kerf() {
+    // given: a square on an acrylic layer, a square on a wood layer, a square whose sides are on two layers, and a
+    // square whose sides are on the acrylic layer named in different cases
+    let square = |x: f64, layers: [&str; 4]| {
+        let corners = [(x, 0.), (x + 10., 0.), (x + 10., 10.), (x, 10.)];
+        (0..4)
+            .map(|i| {
+                let ((x1, y1), (x2, y2)) = (corners[i], corners[(i + 1) % 4]);
+                let mut side = line(x1, y1, x2, y2);
+                side.common.layer = String::from(layers[i]);
+                side
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut entities = square(0., ["acrylic"; 4]);
+    entities.extend(square(20., ["wood"; 4]));
+    entities.extend(square(40., ["acrylic", "acrylic", "wood", "wood"]));
+    entities.extend(square(60., ["Acrylic", "ACRYLIC", "acrylic", "Acrylic"]));
+    let drawing = drawing_of(entities);
+
+    // when: we offset acrylic by 0.1, and everything else by 0.3
+    let mut kerfs = KerfMap::new(0.3);
+    kerfs.set_layer("acrylic", 0.1);
+    let (offset, _) = offset_with_kerfs(&drawing, &kerfs, &OffsetOptions::default());
+
+    // then: each square grows by the kerf for its layer
+    let lowest_y = |layer: &str, min_x: f64, max_x: f64| {
+        offset
+            .entities
+            .iter()
+            .filter(|e| e.common.layer.eq_ignore_ascii_case(layer))
+            .filter_map(open_ends_of_entity)
+            .flat_map(|(start, end)| vec![start, end])
+            .filter(|pt| pt.x >= min_x && pt.x <= max_x)
+            .map(|pt| pt.y)
+            .fold(f64::INFINITY, f64::min)
+    };
+    assert!((lowest_y("acrylic", -1., 11.) + 0.1).abs() < 1e-6);
+    assert!((lowest_y("wood", 19., 31.) + 0.3).abs() < 1e-6);
+
+    // and: the square whose sides are on different layers is not joined up, so it is left as it was
+    assert!(lowest_y("acrylic", 39., 51.).abs() < 1e-6);
+    assert!(lowest_y("wood", 39., 51.).abs() < 1e-6);
+
+    // and: the square whose layer is named in different cases is joined up and offset by the kerf for acrylic
+    assert!((lowest_y("acrylic", 59., 71.) + 0.1).abs() < 1e-6);
+}
+
+#[test]
+fn test_kerf_is_picked_by_color() {
+    // given: a red circle, a green circle, a circle and a fill on a blue engraving layer, a square made of a red
+    // polyline and a line on a red layer, and a square whose sides are red and yellow
+    let colored_circle = |x: f64, layer: &str, color: Color, color_24_bit: i32| {
+        let mut e = circle(x, 0., 5.);
+        e.common.layer = String::from(layer);
+        e.common.color = color;
+        e.common.color_24_bit = color_24_bit;
+        e
+    };
+    let side = |(x1, y1): (f64, f64), (x2, y2): (f64, f64), layer: &str, color: Color| {
+        let mut e = line(x1, y1, x2, y2);
+        e.common.layer = String::from(layer);
+        e.common.color = color;
+        e
+    };
+    let mut fill = Entity::new(EntityType::Solid(Solid::default()));
+    fill.common.layer = String::from("engrave");
+    let mut three_sides = lwpolyline(&[(60., 0., 0.), (70., 0., 0.), (70., 10., 0.), (60., 10., 0.)], false);
+    three_sides.common.layer = String::from("red");
+    three_sides.common.color = Color::from_index(1);
+    let mut drawing = drawing_of(vec![
+        colored_circle(0., "0", Color::from_index(1), 0),
+        colored_circle(20., "0", Color::from_index(3), 0x00FF00),
+        colored_circle(40., "engrave", Color::by_layer(), 0),
+        fill,
+        three_sides,
+        side((60., 10.), (60., 0.), "red", Color::by_layer()),
+        side((80., 0.), (90., 0.), "0", Color::from_index(1)),
+        side((90., 0.), (90., 10.), "0", Color::from_index(1)),
+        side((90., 10.), (80., 10.), "0", Color::from_index(2)),
+        side((80., 10.), (80., 0.), "0", Color::from_index(2)),
+    ]);
+    for (name, color) in [("engrave", 5), ("red", 1)] {
+        drawing.layers.push(Layer {
+            name: String::from(name),
+            color: Color::from_index(color),
+            ..Default::default()
+        });
+    }
+    // true colours are only saved from R2004 on
+    drawing.header.version = AcadVersion::R2004;
+
+    // when: we offset red and yellow by 0.1, the green true colour by 0.2, and leave blue alone
+    let mut kerfs = KerfMap::new(0.5);
+    kerfs.set_color(1, 0.1);
+    kerfs.set_color(2, 0.1);
+    kerfs.set_true_color(0x00FF00, 0.2);
+    kerfs.skip_color(5);
+    let (offset, report) = offset_with_kerfs(&drawing, &kerfs, &OffsetOptions::default());
+
+    // then: each circle grows by the kerf for its colour, and the blue one is left as it is
+    let circle_at = |x: f64| {
+        offset
+            .entities
+            .iter()
+            .find(|e| matches!(&e.specific, EntityType::Circle(circle) if (circle.center.x - x).abs() < 1e-6))
+            .unwrap()
+    };
+    let radius_at = |x: f64| match &circle_at(x).specific {
+        EntityType::Circle(circle) => circle.radius,
+        _ => unreachable!(),
+    };
+    assert!((radius_at(0.) - 5.1).abs() < 1e-6);
+    assert!((radius_at(20.) - 5.2).abs() < 1e-6);
+    assert!((radius_at(40.) - 5.).abs() < 1e-6);
+
+    // and: each circle is still drawn in its colour
+    assert_eq!(circle_at(0.).common.color, Color::from_index(1));
+    assert_eq!(circle_at(20.).common.color_24_bit, 0x00FF00);
+    assert_eq!(circle_at(40.).common.color, Color::by_layer());
+
+    // and: the polyline and the line on the red layer are joined up and offset, and each keeps its own colour
+    let in_square = |min_x: f64, max_x: f64| {
+        offset
+            .entities
+            .iter()
+            .filter(|e| open_ends_of_entity(e).is_some_and(|(start, _)| start.x >= min_x && start.x <= max_x))
+            .collect::<Vec<_>>()
+    };
+    let lowest_y = |min_x: f64, max_x: f64| {
+        in_square(min_x, max_x)
+            .into_iter()
+            .filter_map(open_ends_of_entity)
+            .flat_map(|(start, end)| vec![start.y, end.y])
+            .fold(f64::INFINITY, f64::min)
+    };
+    assert!((lowest_y(59., 71.) + 0.1).abs() < 1e-6);
+    assert!(in_square(59., 71.).iter().any(
+        |e| matches!(e.specific, EntityType::LwPolyline(_)) && e.common.color == Color::from_index(1)
+    ));
+    assert!(in_square(59., 71.).iter().any(
+        |e| matches!(e.specific, EntityType::Line(_)) && e.common.color == Color::by_layer()
+    ));
+
+    // and: the red and yellow sides are not joined up, even though they have the same kerf, so they are left as
+    // they were
+    assert!(lowest_y(79., 91.).abs() < 1e-6);
+    let colors = in_square(79., 91.)
+        .iter()
+        .map(|e| e.common.color.index())
+        .collect::<Vec<_>>();
+    assert_eq!(colors.iter().filter(|color| **color == Some(1)).count(), 2);
+    assert_eq!(colors.iter().filter(|color| **color == Some(2)).count(), 2);
+
+    // and: the blue circle and fill are passed through, even though a fill could not be offset
+    let passed_through = report.passed_through();
+    assert_eq!(passed_through.len(), 2);
+    assert!(passed_through.iter().all(|e| e.layer() == "engrave"));
+    assert!(offset
+        .entities
+        .iter()
+        .any(|e| matches!(e.specific, EntityType::Solid(_))));
+}
+
+fn circle_drawing(units: Units, measurement: DrawingUnits) -> Vec<u8> {
+    let mut drawing = drawing_of(vec![circle(0., 0., 1.)]);
+    drawing.header.default_drawing_units = units;
+    drawing.header.drawing_units = measurement;
+    save_to_bytes(&drawing)
+}
+
+fn offset_radius(drawing_bytes: &[u8]) -> f64 {
+    let offset = load(drawing_bytes);
+    match &offset.entities[0].specific {
+        EntityType::Circle(circle) => circle.radius,
+        _ => panic!("circle should still be a circle"),
+    }
+}
+
+#[test]
+fn test_kerf_is_converted_into_drawing_units() {
+    // given: a circle in a drawing that is in inches
+    let drawing_bytes = circle_drawing(Units::Inches, DrawingUnits::Metric);
+
+    // when: we offset it by a kerf of 0.254mm
+    let result = offset_drawing_with_unit(
+        &drawing_bytes,
+        0.254,
+        KerfUnit::Millimeters,
+        &OffsetOptions::default(),
+    )
+    .unwrap();
+
+    // then: it grows by a hundredth of an inch, and nothing had to be assumed about the units
+    assert!((offset_radius(&result.drawing()) - 1.01).abs() < 1e-9);
+    assert_eq!(result.report().assumed_units(), None);
+}
+
+#[test]
+fn test_drawing_without_units_is_assumed_or_refused() {
+    // given: a circle in a metric drawing that does not say what its units are
+    let drawing_bytes = circle_drawing(Units::Unitless, DrawingUnits::Metric);
+
+    // when: we offset it by a kerf of 0.05cm, and by default go by $MEASUREMENT
+    let result = offset_drawing_with_unit(
+        &drawing_bytes,
+        0.05,
+        KerfUnit::Centimeters,
+        &OffsetOptions::default(),
+    )
+    .unwrap();
+
+    // then: the drawing is taken to be in millimetres, and the report says so
+    assert!((offset_radius(&result.drawing()) - 1.5).abs() < 1e-9);
+    assert_eq!(result.report().assumed_units(), Some(KerfUnit::Millimeters));
+
+    // when: we offset it again, refusing to guess the units
+    let options = OffsetOptions {
+        unspecified_units: UnspecifiedUnits::Refuse,
+        ..Default::default()
+    };
+    let error = offset_drawing_with_unit(&drawing_bytes, 0.05, KerfUnit::Centimeters, &options)
+        .unwrap_err();
+
+    // then: the drawing is not offset
+    assert_eq!(error.kind(), "UnspecifiedDrawingUnits");
+}
+
+#[test]
+fn test_block_references_are_expanded_and_offset() {
+    // given: a block with a circle on layer 0, placed once scaled and rotated, and once as a 2x2 array
+    let washer = Block {
+        name: String::from("washer"),
+        entities: vec![circle(0., 0., 5.)],
+        ..Default::default()
+    };
+    let mut scaled = Entity::new(EntityType::Insert(Insert {
+        name: String::from("washer"),
+        location: Point::new(100., 0., 0.),
+        x_scale_factor: 2.,
+        y_scale_factor: 2.,
+        rotation: 90.,
+        ..Default::default()
+    }));
+    scaled.common.layer = String::from("parts");
+    let array = Entity::new(EntityType::Insert(Insert {
+        name: String::from("washer"),
+        location: Point::new(0., 100., 0.),
+        column_count: 2,
+        row_count: 2,
+        column_spacing: 20.,
+        row_spacing: 20.,
+        ..Default::default()
+    }));
+    let mut drawing = drawing_of(vec![scaled, array]);
+    drawing.blocks.push(washer);
+
+    // when: we offset it by 0.1
+    let (offset, _) = offset_by(&drawing, 0.1, &OffsetOptions::default());
+
+    // then: each copy of the circle is offset where it is placed, and takes on the layer of its insert
+    let mut circles = offset
+        .entities
+        .iter()
+        .filter_map(|e| match &e.specific {
+            EntityType::Circle(circle) => Some((circle.center.x, circle.center.y, circle.radius, e.common.layer.clone())),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    circles.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+    let expected = [
+        (0., 100., 5.1, "0"),
+        (0., 120., 5.1, "0"),
+        (20., 100., 5.1, "0"),
+        (20., 120., 5.1, "0"),
+        (100., 0., 10.1, "parts"),
+    ];
+    assert_eq!(circles.len(), expected.len());
+    for ((x, y, radius, layer), (expected_x, expected_y, expected_radius, expected_layer)) in circles.iter().zip(expected.iter()) {
+        assert!((x - expected_x).abs() < 1e-9 && (y - expected_y).abs() < 1e-9);
+        assert!((radius - expected_radius).abs() < 1e-9);
+        assert_eq!(layer, expected_layer);
+    }
+    assert!(!offset
+        .entities
+        .iter()
+        .any(|e| matches!(e.specific, EntityType::Insert(_))));
+}
+
+#[test]
+fn test_entities_from_blocks_get_handles_of_their_own() {
+    // given: a block with a hole, a label and a part number attribute in it, placed twice
+    let label = Block {
+        name: String::from("label"),
+        entities: vec![
+            circle(0., 0., 5.),
+            Entity::new(EntityType::Text(Text {
+                location: Point::new(-2., 0., 0.),
+                value: String::from("M5"),
+                ..Default::default()
+            })),
+            Entity::new(EntityType::AttributeDefinition(AttributeDefinition {
+                text_tag: String::from("PART"),
+                ..Default::default()
+            })),
+        ],
+        ..Default::default()
+    };
+    let placed = |x: f64, part: &str| {
+        Entity::new(EntityType::Insert(Insert {
+            name: String::from("label"),
+            location: Point::new(x, 0., 0.),
+            attributes: vec![Attribute {
+                attribute_tag: String::from("PART"),
+                value: String::from(part),
+                location: Point::new(x, -3., 0.),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }))
+    };
+    let mut drawing = drawing_of(vec![placed(0., "A"), placed(20., "B")]);
+    drawing.blocks.push(label);
+    let drawing = load(&save_to_bytes(&drawing));
+    let insert_handles = drawing
+        .entities
+        .iter()
+        .filter(|e| matches!(e.specific, EntityType::Insert(_)))
+        .map(|e| e.common.handle)
+        .collect::<Vec<_>>();
+
+    // when: we offset it
+    let (offset, report) = offset_by(&drawing, 0.1, &OffsetOptions::default());
+
+    // then: the holes, labels and attributes of both copies are all in the drawing, each with a handle of its own
+    let count = |is_kind: fn(&EntityType) -> bool| offset.entities.iter().filter(|e| is_kind(&e.specific)).count();
+    assert_eq!(count(|e| matches!(e, EntityType::Circle(_))), 2);
+    assert_eq!(count(|e| matches!(e, EntityType::Text(_))), 2);
+    assert_eq!(count(|e| matches!(e, EntityType::Attribute(_))), 2);
+    let mut handles = offset
+        .entities
+        .iter()
+        .map(|e| e.common.handle)
+        .collect::<Vec<_>>();
+    let entity_count = handles.len();
+    handles.sort_unstable();
+    handles.dedup();
+    assert_eq!(handles.len(), entity_count);
+    assert!(!handles.contains(&0));
+
+    // and: the labels and attributes are reported under the handles of the INSERTs that placed them
+    let from_blocks = report
+        .passed_through()
+        .into_iter()
+        .filter(|e| e.entity_type() == "Text" || e.entity_type() == "Attribute")
+        .collect::<Vec<_>>();
+    assert_eq!(insert_handles.len(), 2);
+    assert_eq!(from_blocks.len(), 4);
+    assert!(from_blocks
+        .iter()
+        .all(|e| insert_handles.contains(&e.handle)));
+}
+
+#[test]
+fn test_entities_in_blocks_that_cannot_be_moved_or_cut_are_kept() {
+    // given: a block with a hole, a dimension, a part number attribute and a 3D face in it, placed twice
+    let mut face = Entity::new(EntityType::Face3D(Face3D::default()));
+    face.common.layer = String::from("faces");
+    let bracket = Block {
+        name: String::from("bracket"),
+        entities: vec![
+            circle(0., 0., 5.),
+            Entity::new(EntityType::RotatedDimension(RotatedDimension::default())),
+            Entity::new(EntityType::AttributeDefinition(AttributeDefinition {
+                text_tag: String::from("PART"),
+                ..Default::default()
+            })),
+            face,
+        ],
+        ..Default::default()
+    };
+    let placed = |x: f64| {
+        Entity::new(EntityType::Insert(Insert {
+            name: String::from("bracket"),
+            location: Point::new(x, 0., 0.),
+            attributes: vec![Attribute {
+                attribute_tag: String::from("PART"),
+                value: String::from("B1"),
+                ..Default::default()
+            }],
+            ..Default::default()
+        }))
+    };
+    let mut drawing = drawing_of(vec![placed(0.), placed(20.)]);
+    drawing.blocks.push(bracket);
+    let drawing = load(&save_to_bytes(&drawing));
+    let insert_handles = drawing
+        .entities
+        .iter()
+        .filter(|e| matches!(e.specific, EntityType::Insert(_)))
+        .map(|e| e.common.handle)
+        .collect::<Vec<_>>();
+
+    // when: we offset it
+    let (offset, report) = offset_by(&drawing, 0.1, &OffsetOptions::default());
+
+    // then: both holes are offset where they are placed
+    assert_eq!(circle_radii(&offset), vec![5.1, 5.1]);
+
+    // and: the dimensions and attributes of both copies are kept, and listed as passed through under the INSERTs
+    // that placed them
+    let count = |is_kind: fn(&EntityType) -> bool| offset.entities.iter().filter(|e| is_kind(&e.specific)).count();
+    assert_eq!(count(|e| matches!(e, EntityType::RotatedDimension(_))), 2);
+    assert_eq!(count(|e| matches!(e, EntityType::Attribute(_))), 2);
+    let passed_through = report.passed_through();
+    let types = passed_through
+        .iter()
+        .map(|e| e.entity_type())
+        .collect::<Vec<_>>();
+    assert_eq!(types.iter().filter(|t| **t == "RotatedDimension").count(), 2);
+    assert_eq!(types.iter().filter(|t| **t == "Attribute").count(), 2);
+    assert!(passed_through
+        .iter()
+        .filter(|e| e.entity_type() == "RotatedDimension" || e.entity_type() == "Attribute")
+        .all(|e| insert_handles.contains(&e.handle)));
+
+    // and: the 3D faces are kept and reported, rather than the INSERTs being left unexpanded
+    assert_eq!(count(|e| matches!(e, EntityType::Face3D(_))), 2);
+    assert_eq!(count(|e| matches!(e, EntityType::Insert(_))), 0);
+    let errors = report.entity_errors();
+    assert_eq!(errors.len(), 2);
+    assert!(errors
+        .iter()
+        .all(|e| e.kind() == "UnsupportedEntity" && e.layer() == "faces" && insert_handles.contains(&e.handle)));
+}
+
+#[test]
+fn test_open_contours_are_left_refused_or_outlined() {
+    // given: a circle to cut, and a score line on its own layer
+    let mut score = line(0., 0., 5., 0.);
+    score.common.layer = String::from("score");
+    let drawing = drawing_of(vec![circle(20., 0., 10.), score]);
+
+    // when: we offset it with the default policy
+    let (offset, report) = offset_by(&drawing, 0.5, &OffsetOptions::default());
+
+    // then: the score line is left as it is, and the report says where it is
+    let points = end_points(&offset);
+    assert!(has_point(&points, 0., 0.) && has_point(&points, 5., 0.));
+    let open_paths = report.open_paths();
+    assert_eq!(open_paths.len(), 1);
+    assert_eq!(open_paths[0].layer(), "score");
+    let ends = [
+        (open_paths[0].start_x, open_paths[0].start_y),
+        (open_paths[0].end_x, open_paths[0].end_y),
+    ];
+    assert!(ends.contains(&(0., 0.)) && ends.contains(&(5., 0.)));
+
+    // when: we refuse open contours
+    let options = OffsetOptions {
+        open_contour: OpenContour::Refuse,
+        ..Default::default()
+    };
+    let error = offset_error(&drawing, 0.5, &options);
+
+    // then: the error says where the score line is
+    assert_eq!(error.kind(), "OpenContours");
+    assert_eq!(error.layer().as_deref(), Some("score"));
+    assert!(error.message().contains("(5, 0)"), "{}", error.message());
+
+    // when: we outline open contours with flat ends
+    let options = OffsetOptions {
+        open_contour: OpenContour::Outline,
+        end_cap: EndCap::Flat,
+        ..Default::default()
+    };
+    let (offset, _) = offset_by(&drawing, 0.5, &options);
+
+    // then: the score line becomes a closed slot that is a kerf wide on either side of it
+    let points = end_points(&offset);
+    for &(x, y) in &[(0., -0.5), (0., 0.5), (5., -0.5), (5., 0.5)] {
+        assert!(has_point(&points, x, y), "missing ({}, {})", x, y);
+    }
+    assert!(!has_point(&points, 0., 0.));
+}
+
+#[test]
+fn test_contours_that_cannot_be_offset_are_reported_or_refused() {
+    // given: a circle, a closed polyline whose vertices are all in the same place, a 3D face, and a block reference
+    // whose block is missing
+    let mut degenerate = lwpolyline(&[(40., 0., 0.); 3], true);
+    degenerate.common.layer = String::from("broken");
+    let mut face = Entity::new(EntityType::Face3D(Face3D::default()));
+    face.common.layer = String::from("faces");
+    let missing = Entity::new(EntityType::Insert(Insert {
+        name: String::from("missing"),
+        ..Default::default()
+    }));
+    let drawing = drawing_of(vec![circle(0., 0., 10.), degenerate.clone(), face, missing]);
+
+    // when: we offset it, reporting contours that cannot be offset
+    let (offset, report) = offset_by(&drawing, 0.5, &OffsetOptions::default());
+
+    // then: the circle is still offset
+    assert!(offset.entities.iter().any(|e| match &e.specific {
+        EntityType::Circle(circle) => (circle.radius - 10.5).abs() < 1e-9,
+        _ => false,
+    }));
+
+    // and: the polyline is listed as not having been offset
+    let errors = report.contour_errors();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].kind(), "CannotOffsetEmptyContour");
+    let entities = errors[0].entities();
+    assert_eq!(entities.len(), 1);
+    assert_eq!(entities[0].entity_type(), "LwPolyline");
+    assert_eq!(entities[0].layer(), "broken");
+
+    // and: the polyline is left in the drawing as it was, under the handle it was listed with
+    let polyline = offset
+        .entities
+        .iter()
+        .find(|e| matches!(e.specific, EntityType::LwPolyline(_)))
+        .expect("the polyline is missing from the offset drawing");
+    assert_eq!(polyline.common.handle, entities[0].handle);
+    assert_eq!(polyline.common.layer, "broken");
+    match &polyline.specific {
+        EntityType::LwPolyline(poly) => {
+            assert!(poly.get_is_closed());
+            assert_eq!(poly.vertices.len(), 3);
+            assert!(poly.vertices.iter().all(|v| v.x == 40. && v.y == 0.));
+        }
+        _ => unreachable!(),
+    }
+
+    // and: the 3D face and the block reference are listed as not having been offset, and are left in the drawing
+    let errors = report.entity_errors();
+    let kinds = errors.iter().map(|e| e.kind()).collect::<Vec<_>>();
+    assert_eq!(kinds, vec!["UnsupportedEntity", "MissingBlock"]);
+    assert_eq!(errors[0].layer(), "faces");
+    assert!(offset
+        .entities
+        .iter()
+        .any(|e| matches!(e.specific, EntityType::Face3D(_))));
+    assert!(offset
+        .entities
+        .iter()
+        .any(|e| matches!(&e.specific, EntityType::Insert(insert) if insert.name == "missing")));
+
+    // when: we offset it again, refusing contours that cannot be offset
+    let error = offset_error(&drawing, 0.5, &refusing_failures());
+
+    // then: the drawing is not offset, because of the first entity that could not be made into a contour
+    assert_eq!(error.kind(), "UnsupportedEntity");
+
+    // and: without the 3D face and block reference, it is not offset because of the polyline
+    let drawing = drawing_of(vec![circle(0., 0., 10.), degenerate]);
+    let error = offset_error(&drawing, 0.5, &refusing_failures());
+    assert_eq!(error.kind(), "CannotOffsetEmptyContour");
+
+    // and: it is not offset by the entry points that give back only the drawing, which have nowhere to report it
+    let drawing_bytes = save_to_bytes(&drawing);
+    let error = offset_drawing(&drawing_bytes, 0.5).unwrap_err();
+    assert_eq!(error.kind(), "CannotOffsetEmptyContour");
+    let error = offset_drawing_with_options(&drawing_bytes, 0.5, &OffsetOptions::default()).unwrap_err();
+    assert_eq!(error.kind(), "CannotOffsetEmptyContour");
+}
+
+/// The way contours were joined before the end points were put in a spatial index: the contour at the front of the
+/// queue is combined with the first contour after it that it can be combined with, and the result goes to the back
+fn collapse_contours_by_searching(mut contours: Vec<Contour>, tolerance: f64) -> Vec<Contour> {
+    let mut final_contours = Vec::new();
+    while !contours.is_empty() {
+        let head = contours.remove(0);
+        let partner = contours.iter().position(|other| {
+            head.clone()
+                .combine_attempt(other.clone(), tolerance)
+                .is_ok()
+        });
+        match partner {
+            None => final_contours.push(head),
+            Some(i) => {
+                let other = contours.remove(i);
+                contours.push(head.combine_attempt(other, tolerance).unwrap());
+            }
+        }
+    }
+    final_contours
+}
+
+/// Everything about a contour that joining can change, in a form that can be compared
+fn contour_shape(contour: &Contour) -> String {
+    let segments = contour
+        .segments
+        .iter()
+        .map(|segment| format!("{:?} (from {:X})", segment.shape, segment.source.common.handle))
+        .collect::<Vec<_>>();
+    format!("{:?} {}", contour.end_points, segments.join(", "))
+}
+
+#[test]
+fn test_joining_through_the_index_matches_searching() {
+    // given: the contours of the second example drawing, copies of them spread out in a grid, and the same copies
+    // in a shuffled order
+    let tile = example_contours();
+    let tiled = tiled_example(3);
+    // a fixed shuffle, so that the test always does the same thing
+    let mut shuffled = tiled.clone();
+    let mut seed = 12345u64;
+    for i in (1..shuffled.len()).rev() {
+        seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        shuffled.swap(i, (seed >> 33) as usize % (i + 1));
+    }
+    let tolerance = OffsetOptions::default().join_tolerance;
+
+    for contours in [tile, tiled, shuffled] {
+        // when: we join them up through the index, and by searching through all of them
+        let indexed = collapse_contours(contours.clone(), tolerance).unwrap();
+        let searched = collapse_contours_by_searching(contours, tolerance);
+
+        // then: both make the same contours, in the same order
+        assert_eq!(
+            indexed.iter().map(contour_shape).collect::<Vec<_>>(),
+            searched.iter().map(contour_shape).collect::<Vec<_>>()
+        );
+    }
 }
@@ -0,0 +1,140 @@
+//! Drawings for the tests to offset, and the steps that every test goes through to offset them
+pub mod tiling;
+
+use dxf::entities::*;
+use dxf::enums::AcadVersion;
+use dxf::{Drawing, LwPolylineVertex, Point};
+use kerfadjusterlogic::contour::open_ends_of_entity;
+use kerfadjusterlogic::errors::KerfAdjustmentError;
+use kerfadjusterlogic::kerf::KerfMap;
+use kerfadjusterlogic::{offset_drawing_with_kerf_map, offset_drawing_with_report};
+use kerfadjusterlogic::options::{FailedContour, OffsetOptions};
+use kerfadjusterlogic::report::OffsetReport;
+use nalgebra::Vector3;
+
+pub fn save_to_bytes(drawing: &Drawing) -> Vec<u8> {
+    let mut drawing_bytes = Vec::new();
+    drawing.save(&mut drawing_bytes).unwrap();
+    drawing_bytes
+}
+
+pub fn load(drawing_bytes: &[u8]) -> Drawing {
+    Drawing::load(&mut &drawing_bytes[..]).unwrap()
+}
+
+pub fn line(x1: f64, y1: f64, x2: f64, y2: f64) -> Entity {
+    Entity::new(EntityType::Line(Line::new(
+        Point::new(x1, y1, 0.),
+        Point::new(x2, y2, 0.),
+    )))
+}
+
+pub fn circle(x: f64, y: f64, radius: f64) -> Entity {
+    Entity::new(EntityType::Circle(Circle::new(Point::new(x, y, 0.), radius)))
+}
+
+/// A polyline through the vertices, given as `(x, y, bulge)`
+pub fn lwpolyline(vertices: &[(f64, f64, f64)], is_closed: bool) -> Entity {
+    let mut poly = LwPolyline::default();
+    poly.set_is_closed(is_closed);
+    for &(x, y, bulge) in vertices {
+        poly.vertices.push(LwPolylineVertex {
+            x,
+            y,
+            bulge,
+            ..Default::default()
+        });
+    }
+    Entity::new(EntityType::LwPolyline(poly))
+}
+
+/// The smallest and largest x and y of a polyline, as `(min_x, min_y, max_x, max_y)`. A bulge is taken to reach as
+/// far as the middle of its arc, which is only the furthest it reaches for arcs of up to a semicircle.
+pub fn lwpolyline_extents(poly: &LwPolyline) -> (f64, f64, f64, f64) {
+    let count = poly.vertices.len();
+    let segments = if poly.get_is_closed() { count } else { count - 1 };
+    let mut points = poly.vertices.iter().map(|v| (v.x, v.y)).collect::<Vec<_>>();
+    for i in 0..segments {
+        let (from, to) = (&poly.vertices[i], &poly.vertices[(i + 1) % count]);
+        // the middle of the arc is off the middle of the chord by the bulge times half the chord, to the right of it
+        // for an arc that goes anticlockwise
+        let (dx, dy) = (to.x - from.x, to.y - from.y);
+        let sagitta = from.bulge / 2.;
+        points.push(((from.x + to.x) / 2. + dy * sagitta, (from.y + to.y) / 2. - dx * sagitta));
+    }
+    points.iter().fold(
+        (f64::INFINITY, f64::INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+        |(min_x, min_y, max_x, max_y), &(x, y)| (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y)),
+    )
+}
+
+/// A drawing with nothing but the entities in it. It is saved as R2000, since older versions leave out entities like
+/// LWPOLYLINE and SPLINE.
+pub fn drawing_of(entities: Vec<Entity>) -> Drawing {
+    let mut drawing = Drawing {
+        entities,
+        ..Default::default()
+    };
+    drawing.header.version = AcadVersion::R2000;
+    drawing
+}
+
+/// Options that refuse to offset drawings with contours or entities that cannot be offset
+pub fn refusing_failures() -> OffsetOptions {
+    OffsetOptions {
+        failed_contour: FailedContour::Refuse,
+        ..Default::default()
+    }
+}
+
+/// Saves the drawing, offsets it by `kerf`, and loads the offset drawing back in
+pub fn offset_by(drawing: &Drawing, kerf: f64, options: &OffsetOptions) -> (Drawing, OffsetReport) {
+    let result = offset_drawing_with_report(&save_to_bytes(drawing), kerf, options).unwrap();
+    (load(&result.drawing()), result.report())
+}
+
+/// Saves the drawing, offsets it by the kerfs in `kerfs`, and loads the offset drawing back in
+pub fn offset_with_kerfs(
+    drawing: &Drawing,
+    kerfs: &KerfMap,
+    options: &OffsetOptions,
+) -> (Drawing, OffsetReport) {
+    let result = offset_drawing_with_kerf_map(&save_to_bytes(drawing), kerfs, options).unwrap();
+    (load(&result.drawing()), result.report())
+}
+
+/// Saves the drawing, and gives back why it cannot be offset by `kerf`
+pub fn offset_error(drawing: &Drawing, kerf: f64, options: &OffsetOptions) -> KerfAdjustmentError {
+    offset_drawing_with_report(&save_to_bytes(drawing), kerf, options).unwrap_err()
+}
+
+/// The start and end of every entity in the drawing that has them
+pub fn end_points(drawing: &Drawing) -> Vec<Vector3<f64>> {
+    drawing
+        .entities
+        .iter()
+        .filter_map(open_ends_of_entity)
+        .flat_map(|(start, end)| vec![start, end])
+        .collect()
+}
+
+/// Whether one of the points is at (x, y)
+pub fn has_point(points: &[Vector3<f64>], x: f64, y: f64) -> bool {
+    points
+        .iter()
+        .any(|pt| (pt.x - x).abs() < 1e-9 && (pt.y - y).abs() < 1e-9)
+}
+
+/// The radii of the circles in the drawing, smallest first
+pub fn circle_radii(drawing: &Drawing) -> Vec<f64> {
+    let mut radii = drawing
+        .entities
+        .iter()
+        .filter_map(|e| match &e.specific {
+            EntityType::Circle(circle) => Some(circle.radius),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    radii.sort_by(f64::total_cmp);
+    radii
+}
@@ -0,0 +1,58 @@
+//! Copies of the second example drawing laid out in a grid, for joining lots of contours. Shared by the tests and the
+//! benchmarks.
+use dxf::entities::*;
+use dxf::Drawing;
+use kerfadjusterlogic::contour::{extents_size, Contour};
+
+/// Moves an entity across by `dx` and up by `dy`
+pub fn translate(mut e: Entity, dx: f64, dy: f64) -> Entity {
+    match &mut e.specific {
+        EntityType::Line(line) => {
+            line.p1.x += dx;
+            line.p1.y += dy;
+            line.p2.x += dx;
+            line.p2.y += dy;
+        }
+        EntityType::Arc(arc) => {
+            arc.center.x += dx;
+            arc.center.y += dy;
+        }
+        EntityType::Circle(circle) => {
+            circle.center.x += dx;
+            circle.center.y += dy;
+        }
+        _ => (),
+    }
+    e
+}
+
+/// The contours of the second example drawing, one for each entity, ready to be joined up
+pub fn example_contours() -> Vec<Contour> {
+    Drawing::load_file("../example_dxf2.DXF")
+        .unwrap()
+        .entities
+        .into_iter()
+        .map(Contour::create_from_entity)
+        .collect()
+}
+
+/// Copies of the example drawing laid out in a grid that is `copies` wide and `copies` tall, as contours that are
+/// ready to be joined up. The entities of each copy have handles of their own.
+pub fn tiled_example(copies: usize) -> Vec<Contour> {
+    let tile = example_contours();
+    // No copy is wider than the diagonal of the drawing, so the copies do not touch
+    let spacing = extents_size(&tile) * 1.5;
+
+    let mut contours = Vec::with_capacity(tile.len() * copies * copies);
+    for row in 0..copies {
+        for column in 0..copies {
+            let copy = (row * copies + column) as u32;
+            let (dx, dy) = (column as f64 * spacing, row as f64 * spacing);
+            contours.extend(tile.iter().flat_map(|c| c.to_entities()).map(|mut entity| {
+                entity.common.handle += copy * 0x10000;
+                Contour::create_from_entity(translate(entity, dx, dy))
+            }));
+        }
+    }
+    contours
+}
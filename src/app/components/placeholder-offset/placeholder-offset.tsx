@@ -16,8 +16,39 @@ export const PlaceholderOffsetUI: React.FC = () => {
             return;
         }
         // eslint-disable-next-line @typescript-eslint/no-explicit-any
-        const { offset_drawing } = (await import("kerfadjusterlogic")) as any;
-        const new_drawing = Buffer.from(offset_drawing(file, kerfThickness));
+        const { offset_drawing_with_report, OffsetOptions } = (await import("kerfadjusterlogic")) as any;
+        let new_drawing: Buffer;
+        // eslint-disable-next-line @typescript-eslint/no-explicit-any
+        let report: any;
+        try {
+            const result = offset_drawing_with_report(file, kerfThickness, new OffsetOptions());
+            new_drawing = Buffer.from(result.drawing);
+            report = result.report;
+        } catch (error) {
+            // errors from offsetting carry the entity that caused them, if there was one
+            // eslint-disable-next-line @typescript-eslint/no-explicit-any
+            const { message, layer, handle } = error as any;
+            const entity = handle !== undefined ? `\n\nEntity handle: ${handle.toString(16).toUpperCase()}, layer: ${layer}` : "";
+            alert(`${message ?? error}${entity}`);
+            return;
+        }
+
+        // contours and entities that could not be offset are left in the drawing at their original size, which makes
+        // parts the wrong size, so the user has to know about them before cutting it
+        const formatHandle = (handle: number) => handle.toString(16).toUpperCase();
+        const notOffset: string[] = [
+            // eslint-disable-next-line @typescript-eslint/no-explicit-any
+            ...report.contour_errors.map((error: any) => {
+                // eslint-disable-next-line @typescript-eslint/no-explicit-any
+                const entities = error.entities.map((e: any) => `${e.entity_type} ${formatHandle(e.handle)} (layer: ${e.layer})`);
+                return `${error.message}\nEntities: ${entities.join(", ")}`;
+            }),
+            // eslint-disable-next-line @typescript-eslint/no-explicit-any
+            ...report.entity_errors.map((error: any) => `${error.message}\nEntity handle: ${formatHandle(error.handle)}, layer: ${error.layer}`),
+        ];
+        if (notOffset.length > 0 && !confirm(`These were not adjusted for the kerf, and are left at their original size:\n\n${notOffset.join("\n\n")}\n\nDownload the offset DXF anyway?`)) {
+            return;
+        }
         const new_drawing_data_uri = `data:application/dxf;base64,${new_drawing.toString("base64")}`;
 
         // sort of a hack to automatically download the newly offset dxf
@@ -61,7 +92,6 @@ export const PlaceholderOffsetUI: React.FC = () => {
                     <li>make it work properly</li>
                     <li>Show DXF to user, and compare original version to offset version</li>
                     <li>Testing on a variety of DXF files and entity types beyond line, circle, arc</li>
-                    <li>Explicit error messages if an entity in the DXF file is not supported</li>
                     <li>Allow user to change whether a contour should be offset inwards or outwards</li>
                 </ul>
             </div>